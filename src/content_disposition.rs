@@ -0,0 +1,261 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::anyhow;
+use percent_encoding::percent_decode_str;
+use tracing::{debug, warn};
+
+/// Longest file name (in bytes) most filesystems accept.
+const MAX_FILE_NAME_BYTES: usize = 255;
+
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Extracts the file name from a `Content-Disposition` header value as described by RFC 6266.
+///
+/// `filename*` (RFC 5987 extended notation) is preferred over `filename` when both are present.
+/// The result is not sanitized; see [`sanitize_filename`].
+pub fn parse_filename(header: &str) -> Option<String> {
+    let mut filename = None;
+    let mut extended_filename = None;
+    for param in split_params(header).into_iter().skip(1) {
+        let Some((name, value)) = param.split_once('=') else {
+            continue;
+        };
+        match name.trim().to_ascii_lowercase().as_str() {
+            "filename*" => extended_filename = decode_extended_value(value.trim()),
+            "filename" => filename = Some(unquote(value.trim())),
+            _ => {}
+        }
+    }
+    extended_filename.or(filename)
+}
+
+/// Splits a header value on `;`, ignoring separators inside quoted strings.
+fn split_params(header: &str) -> Vec<String> {
+    let mut params = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for c in header.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            ';' if !in_quotes => {
+                params.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    params.push(current);
+    params
+}
+
+/// Removes the quotes and backslash escapes from a quoted-string, leaving tokens untouched.
+fn unquote(value: &str) -> String {
+    let Some(inner) = value
+        .strip_prefix('"')
+        .map(|v| v.strip_suffix('"').unwrap_or(v))
+    else {
+        return value.to_string();
+    };
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unquoted.extend(chars.next()),
+            _ => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+/// Decodes an RFC 5987 `charset'language'percent-encoded` value.
+fn decode_extended_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next()?.trim();
+    let _language = parts.next()?;
+    let encoded = unquote(parts.next()?);
+    let bytes = percent_decode_str(&encoded).collect::<Vec<u8>>();
+    if charset.eq_ignore_ascii_case("utf-8") {
+        String::from_utf8(bytes).ok()
+    } else if charset.eq_ignore_ascii_case("iso-8859-1") {
+        Some(bytes.into_iter().map(char::from).collect())
+    } else {
+        warn!(charset, "Unsupported charset in Content-Disposition");
+        None
+    }
+}
+
+/// Turns an untrusted file name into one that is safe to create on any platform.
+///
+/// Any directory part is dropped, reserved and control characters are replaced, and names that
+/// would refer to something other than a regular file (such as `..`) are rejected.
+pub fn sanitize_filename(name: &str) -> Option<String> {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let replaced = base
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    let trimmed = replaced.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    if trimmed.is_empty() {
+        return None;
+    }
+
+    let stem = trimmed.split('.').next().unwrap_or_default();
+    let mut sanitized = if WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|r| r.eq_ignore_ascii_case(stem))
+    {
+        format!("_{trimmed}")
+    } else {
+        trimmed.to_string()
+    };
+
+    if sanitized.len() > MAX_FILE_NAME_BYTES {
+        let extension = Path::new(&sanitized)
+            .extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default();
+        let mut end = MAX_FILE_NAME_BYTES.saturating_sub(extension.len());
+        while !sanitized.is_char_boundary(end) {
+            end -= 1;
+        }
+        sanitized = format!("{}{}", &sanitized[..end], extension);
+    }
+
+    if sanitized != name {
+        debug!(original = name, sanitized, "Sanitized file name");
+    }
+    Some(sanitized)
+}
+
+/// Joins `file_name` onto `directory`, refusing anything that would end up outside of it.
+pub fn join_within(directory: &Path, file_name: &str) -> anyhow::Result<PathBuf> {
+    let mut components = Path::new(file_name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(directory.join(file_name)),
+        _ => Err(anyhow!(
+            "Refusing to write '{}' outside of '{}'",
+            file_name,
+            directory.to_string_lossy()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_extended_filename() {
+        assert_eq!(
+            parse_filename("attachment; filename*=UTF-8''%E2%82%AC%20rates.safetensors"),
+            Some("€ rates.safetensors".to_string())
+        );
+        assert_eq!(
+            parse_filename("attachment; filename*=iso-8859-1'en'caf%E9.pt"),
+            Some("café.pt".to_string())
+        );
+    }
+
+    #[test]
+    fn prefers_extended_filename() {
+        assert_eq!(
+            parse_filename("attachment; filename=\"fallback.bin\"; filename*=UTF-8''real%20name.ckpt"),
+            Some("real name.ckpt".to_string())
+        );
+        assert_eq!(
+            parse_filename("attachment; filename*=UTF-8''real%20name.ckpt; filename=\"fallback.bin\""),
+            Some("real name.ckpt".to_string())
+        );
+        assert_eq!(
+            parse_filename("attachment; filename=\"fallback.bin\"; filename*=KOI8-R''%C1.pt"),
+            Some("fallback.bin".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_quoted_and_trailing_params() {
+        assert_eq!(
+            parse_filename("attachment; filename=model.safetensors"),
+            Some("model.safetensors".to_string())
+        );
+        assert_eq!(
+            parse_filename("attachment; FileName=\"model; v2.safetensors\"; size=123"),
+            Some("model; v2.safetensors".to_string())
+        );
+        assert_eq!(
+            parse_filename(r#"attachment; filename="say \"hi\".pt"; creation-date="today""#),
+            Some("say \"hi\".pt".to_string())
+        );
+        assert_eq!(parse_filename("inline"), None);
+        assert_eq!(parse_filename("attachment; size=123"), None);
+    }
+
+    #[test]
+    fn drops_directories() {
+        assert_eq!(sanitize_filename("../../.bashrc"), Some("bashrc".to_string()));
+        assert_eq!(sanitize_filename("/etc/passwd"), Some("passwd".to_string()));
+        assert_eq!(
+            sanitize_filename(r"C:\Windows\System32\evil.dll"),
+            Some("evil.dll".to_string())
+        );
+        assert_eq!(sanitize_filename(r"..\..\evil.dll"), Some("evil.dll".to_string()));
+        assert_eq!(
+            parse_filename("attachment; filename=\"../../.bashrc\"")
+                .as_deref()
+                .and_then(sanitize_filename),
+            Some("bashrc".to_string())
+        );
+    }
+
+    #[test]
+    fn replaces_reserved_characters() {
+        assert_eq!(sanitize_filename("a:b?c*.pt"), Some("a_b_c_.pt".to_string()));
+        assert_eq!(sanitize_filename("tab\there.pt"), Some("tab_here.pt".to_string()));
+    }
+
+    #[test]
+    fn prefixes_windows_reserved_names() {
+        assert_eq!(sanitize_filename("CON"), Some("_CON".to_string()));
+        assert_eq!(sanitize_filename("nul.txt"), Some("_nul.txt".to_string()));
+        assert_eq!(sanitize_filename("com1.tar.gz"), Some("_com1.tar.gz".to_string()));
+        assert_eq!(sanitize_filename("CONSOLE.pt"), Some("CONSOLE.pt".to_string()));
+    }
+
+    #[test]
+    fn rejects_empty_and_dot_only_names() {
+        for name in ["", ".", "..", "...", " . ", "models/", r"models\"] {
+            assert_eq!(sanitize_filename(name), None, "{name:?}");
+        }
+    }
+
+    #[test]
+    fn truncates_long_names_keeping_extension() {
+        let name = format!("{}.safetensors", "é".repeat(200));
+        let sanitized = sanitize_filename(&name).unwrap();
+        assert!(sanitized.len() <= MAX_FILE_NAME_BYTES);
+        assert!(sanitized.ends_with(".safetensors"));
+    }
+
+    #[test]
+    fn joins_only_plain_names() {
+        let directory = Path::new("models");
+        assert_eq!(
+            join_within(directory, "model.pt").unwrap(),
+            directory.join("model.pt")
+        );
+        for name in ["", "..", "../model.pt", "/etc/passwd", "nested/model.pt"] {
+            assert!(join_within(directory, name).is_err(), "{name:?}");
+        }
+    }
+}
@@ -0,0 +1,611 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context};
+use serde::Serialize;
+use tracing::debug;
+
+use crate::hashing::{hash_file, HashKind};
+use crate::{get_part_path, persist_download};
+use crate::pickle::{self, Value, SAFE_GLOBALS};
+use crate::safetensors::{self, TensorInfo};
+
+/// The storage classes torch pickles, with the safetensors dtype and byte size of their elements.
+const STORAGE_DTYPES: [(&str, &str, u64); 10] = [
+    ("torch.DoubleStorage", "F64", 8),
+    ("torch.FloatStorage", "F32", 4),
+    ("torch.HalfStorage", "F16", 2),
+    ("torch.BFloat16Storage", "BF16", 2),
+    ("torch.LongStorage", "I64", 8),
+    ("torch.IntStorage", "I32", 4),
+    ("torch.ShortStorage", "I16", 2),
+    ("torch.CharStorage", "I8", 1),
+    ("torch.ByteStorage", "U8", 1),
+    ("torch.BoolStorage", "BOOL", 1),
+];
+
+/// The untyped storages torch 2 pickles tensors of dtypes that have no storage class above with,
+/// such as float8. The dtype is passed to `_rebuild_tensor_v3` instead.
+const UNTYPED_STORAGES: [&str; 2] = ["torch.UntypedStorage", "torch.storage.UntypedStorage"];
+
+/// The dtypes `_rebuild_tensor_v3` is pickled with, with the safetensors dtype and byte size of
+/// their elements.
+const TENSOR_DTYPES: [(&str, &str, u64); 15] = [
+    ("torch.float64", "F64", 8),
+    ("torch.float32", "F32", 4),
+    ("torch.float16", "F16", 2),
+    ("torch.bfloat16", "BF16", 2),
+    ("torch.float8_e4m3fn", "F8_E4M3", 1),
+    ("torch.float8_e5m2", "F8_E5M2", 1),
+    ("torch.int64", "I64", 8),
+    ("torch.int32", "I32", 4),
+    ("torch.int16", "I16", 2),
+    ("torch.int8", "I8", 1),
+    ("torch.uint64", "U64", 8),
+    ("torch.uint32", "U32", 4),
+    ("torch.uint16", "U16", 2),
+    ("torch.uint8", "U8", 1),
+    ("torch.bool", "BOOL", 1),
+];
+
+/// What [`convert`] wrote.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Conversion {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub tensor_count: usize,
+    /// State dict entries that aren't tensors, such as `global_step`.
+    pub skipped_keys: Vec<String>,
+    /// Whether the source was removed.
+    pub replaced: bool,
+}
+
+impl fmt::Display for Conversion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} -> {}",
+            self.source.to_string_lossy(),
+            self.target.to_string_lossy()
+        )?;
+        writeln!(f, "  Tensors: {}", self.tensor_count)?;
+        if !self.skipped_keys.is_empty() {
+            writeln!(f, "  Skipped: {}", self.skipped_keys.join(", "))?;
+        }
+        if self.replaced {
+            writeln!(f, "  Removed the original")?;
+        }
+        Ok(())
+    }
+}
+
+/// A tensor in a checkpoint: a strided view into one of its storages.
+#[derive(Debug, Clone, PartialEq)]
+struct TensorRef {
+    storage_key: String,
+    dtype: &'static str,
+    element_size: u64,
+    /// In elements, like the shape and stride.
+    offset: u64,
+    shape: Vec<u64>,
+    stride: Vec<u64>,
+}
+
+impl TensorRef {
+    fn element_count(&self) -> u64 {
+        self.shape.iter().fold(1, |count, d| count.saturating_mul(*d))
+    }
+
+    fn is_contiguous(&self) -> bool {
+        let mut expected = 1;
+        for (dim, stride) in self.shape.iter().zip(&self.stride).rev() {
+            if *dim != 1 && *stride != expected {
+                return false;
+            }
+            expected = expected.saturating_mul(*dim);
+        }
+        true
+    }
+
+    /// Copies the elements of the view out of `storage` in row-major order.
+    fn read(&self, storage: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let count = self.element_count();
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        let last = self
+            .shape
+            .iter()
+            .zip(&self.stride)
+            .fold(self.offset, |last, (d, s)| last.saturating_add((d - 1).saturating_mul(*s)));
+        if last.saturating_add(1).saturating_mul(self.element_size) > storage.len() as u64 {
+            return Err(anyhow!(
+                "reaches past the end of storage {} ({} bytes)",
+                self.storage_key,
+                storage.len()
+            ));
+        }
+
+        let size = self.element_size as usize;
+        if self.is_contiguous() {
+            let start = self.offset as usize * size;
+            return Ok(storage[start..start + count as usize * size].to_vec());
+        }
+        let mut data = Vec::with_capacity(count as usize * size);
+        let mut index = vec![0u64; self.shape.len()];
+        for _ in 0..count {
+            let position = index
+                .iter()
+                .zip(&self.stride)
+                .fold(self.offset, |p, (i, s)| p + i * s) as usize;
+            data.extend_from_slice(&storage[position * size..(position + 1) * size]);
+            for dim in (0..index.len()).rev() {
+                index[dim] += 1;
+                if index[dim] < self.shape[dim] {
+                    break;
+                }
+                index[dim] = 0;
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// Where the converted copy of `path` goes: next to it, with a `.safetensors` extension.
+pub fn get_target_path(path: &Path) -> PathBuf {
+    path.with_extension("safetensors")
+}
+
+/// Converts the state dict in the torch checkpoint at `path` to a safetensors file next to it,
+/// removing the checkpoint afterwards if `replace` is set.
+///
+/// The checkpoint is loaded with [`pickle::load`], so nothing in it is run. Only the zip format
+/// torch has saved since 1.6 is supported, with tensors in typed storages or, as torch 2 saves
+/// dtypes like float8, in untyped storages along with their dtype.
+#[tracing::instrument(level = "debug")]
+pub fn convert(path: &Path, replace: bool, overwrite: bool) -> anyhow::Result<Conversion> {
+    let target = get_target_path(path);
+    if target == path {
+        return Err(anyhow!("'{}' is already a safetensors file", path.to_string_lossy()));
+    }
+    if target.exists() && !overwrite {
+        return Err(anyhow!(
+            "'{}' already exists. Pass --overwrite to replace it",
+            target.to_string_lossy()
+        ));
+    }
+
+    let file =
+        File::open(path).with_context(|| format!("Failed to open '{}'", path.to_string_lossy()))?;
+    let mut archive = zip::ZipArchive::new(file).with_context(|| {
+        format!(
+            "'{}' is not a zip checkpoint. Only checkpoints saved by torch 1.6 or later can be converted",
+            path.to_string_lossy()
+        )
+    })?;
+    let pickle_name = archive
+        .file_names()
+        .find(|n| *n == "data.pkl" || n.ends_with("/data.pkl"))
+        .map(str::to_string)
+        .ok_or(anyhow!("'{}' has no data.pkl", path.to_string_lossy()))?;
+    let prefix = pickle_name.trim_end_matches("data.pkl").to_string();
+    if let Ok(mut entry) = archive.by_name(&format!("{prefix}byteorder")) {
+        let mut byte_order = String::new();
+        entry.read_to_string(&mut byte_order)?;
+        if byte_order.trim() != "little" {
+            return Err(anyhow!("'{}' is stored {byte_order}-endian", path.to_string_lossy()));
+        }
+    }
+
+    let mut data = Vec::new();
+    archive.by_name(&pickle_name)?.read_to_end(&mut data)?;
+    let root = pickle::load(&data, SAFE_GLOBALS)
+        .with_context(|| format!("Failed to load '{}'", path.to_string_lossy()))?;
+    let (tensors, skipped_keys) = get_state_dict(&root)
+        .with_context(|| format!("Failed to read the state dict of '{}'", path.to_string_lossy()))?;
+
+    let mut infos = BTreeMap::new();
+    let mut offset: u64 = 0;
+    for (name, tensor) in &tensors {
+        let end = tensor
+            .element_count()
+            .checked_mul(tensor.element_size)
+            .and_then(|size| offset.checked_add(size))
+            .ok_or(anyhow!("Tensor '{name}' is too large to convert"))?;
+        infos.insert(
+            name.clone(),
+            TensorInfo {
+                dtype: tensor.dtype.to_string(),
+                shape: tensor.shape.clone(),
+                data_offsets: [offset, end],
+            },
+        );
+        offset = end;
+    }
+    let metadata = get_metadata(path, &skipped_keys)?;
+
+    let part_path = get_part_path(&target);
+    let result = write_safetensors(&part_path, &mut archive, &prefix, &tensors, &infos, &metadata)
+        .and_then(|_| safetensors::inspect(&part_path));
+    if let Err(e) = result {
+        fs::remove_file(&part_path).ok();
+        return Err(e);
+    }
+    // The conversion has to be on disk before the only other copy of the weights is removed.
+    persist_download(&part_path, &target)?;
+    if replace {
+        fs::remove_file(path)
+            .with_context(|| format!("Failed to remove '{}'", path.to_string_lossy()))?;
+    }
+    debug!(target =? &target, tensors = tensors.len(), "Converted checkpoint");
+
+    Ok(Conversion {
+        source: path.to_path_buf(),
+        target,
+        tensor_count: tensors.len(),
+        skipped_keys,
+        replaced: replace,
+    })
+}
+
+/// Collects the tensors of the state dict, which is either the loaded object itself or its
+/// `state_dict` entry, along with the keys that aren't tensors.
+fn get_state_dict(root: &Value) -> anyhow::Result<(BTreeMap<String, TensorRef>, Vec<String>)> {
+    let state_dict = match root.get("state_dict") {
+        Some(s @ Value::Dict(_)) => s,
+        _ => root,
+    };
+    let Value::Dict(entries) = state_dict else {
+        return Err(anyhow!("the checkpoint doesn't contain a dict"));
+    };
+
+    let mut tensors = BTreeMap::new();
+    let mut skipped = Vec::new();
+    for (key, value) in entries {
+        let Some(key) = key.as_str() else {
+            skipped.push(format!("{key:?}"));
+            continue;
+        };
+        match get_tensor(value).with_context(|| format!("tensor '{key}' is malformed"))? {
+            Some(tensor) => {
+                tensors.insert(key.to_string(), tensor);
+            }
+            None => skipped.push(key.to_string()),
+        }
+    }
+    if tensors.is_empty() {
+        return Err(anyhow!("the checkpoint has no tensors"));
+    }
+    Ok((tensors, skipped))
+}
+
+/// Interprets the calls torch pickles a tensor as, or returns `None` for anything else.
+fn get_tensor(value: &Value) -> anyhow::Result<Option<TensorRef>> {
+    let Value::Call { global, args } = value else {
+        return Ok(None);
+    };
+    let arg = |index: usize| args.get(index).ok_or(anyhow!("{global} is missing arguments"));
+    match global.as_str() {
+        "torch._utils._rebuild_parameter" | "torch._utils._rebuild_parameter_with_state" => {
+            get_tensor(arg(0)?)
+        }
+        "torch._tensor._rebuild_from_type_v2" => {
+            let Value::Global(function) = arg(0)? else {
+                return Err(anyhow!("{global} doesn't rebuild with a global"));
+            };
+            let args = arg(2)?
+                .as_items()
+                .ok_or(anyhow!("{global} has no arguments to rebuild with"))?;
+            get_tensor(&Value::Call {
+                global: function.clone(),
+                args: args.to_vec(),
+            })
+        }
+        "torch._utils._rebuild_tensor"
+        | "torch._utils._rebuild_tensor_v2"
+        | "torch._utils._rebuild_tensor_v3" => {
+            let (storage_key, storage_dtype) = get_storage(arg(0)?)?;
+            // v3 passes the dtype as its seventh argument, `(storage, offset, size, stride,
+            // requires_grad, backward_hooks, dtype)`.
+            let (dtype, element_size) = match global.as_str() {
+                "torch._utils._rebuild_tensor_v3" => get_dtype(arg(6)?)?,
+                _ => storage_dtype
+                    .ok_or(anyhow!("{global} refers to an untyped storage without a dtype"))?,
+            };
+            let ints = |value: &Value| -> anyhow::Result<Vec<u64>> {
+                value
+                    .as_items()
+                    .ok_or(anyhow!("{global} has a malformed size or stride"))?
+                    .iter()
+                    .map(|v| {
+                        v.as_int()
+                            .and_then(|i| u64::try_from(i).ok())
+                            .ok_or(anyhow!("{global} has a negative or non-integer dimension"))
+                    })
+                    .collect()
+            };
+            let tensor = TensorRef {
+                storage_key,
+                dtype,
+                element_size,
+                offset: arg(1)?
+                    .as_int()
+                    .and_then(|i| u64::try_from(i).ok())
+                    .ok_or(anyhow!("{global} has a malformed storage offset"))?,
+                shape: ints(arg(2)?)?,
+                stride: ints(arg(3)?)?,
+            };
+            if tensor.shape.len() != tensor.stride.len() {
+                return Err(anyhow!("{global} has a stride that doesn't match its size"));
+            }
+            Ok(Some(tensor))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Reads a `('storage', storage_type, key, location, size)` persistent id, returning the key and,
+/// unless the storage is untyped, the dtype and element size of the storage.
+fn get_storage(value: &Value) -> anyhow::Result<(String, Option<(&'static str, u64)>)> {
+    let Value::PersistentId(id) = value else {
+        return Err(anyhow!("tensor doesn't refer to a storage"));
+    };
+    match id.as_items() {
+        Some([Value::Str(kind), Value::Global(storage_type), key, ..]) if kind == "storage" => {
+            let dtype = match STORAGE_DTYPES.iter().find(|(name, ..)| name == storage_type) {
+                Some((_, dtype, element_size)) => Some((*dtype, *element_size)),
+                None if UNTYPED_STORAGES.contains(&storage_type.as_str()) => None,
+                None => return Err(anyhow!("unsupported storage type {storage_type}")),
+            };
+            let key = match key {
+                Value::Str(key) => key.clone(),
+                Value::Int(key) => key.to_string(),
+                _ => return Err(anyhow!("storage has a malformed key")),
+            };
+            Ok((key, dtype))
+        }
+        _ => Err(anyhow!("unsupported persistent id {id:?}")),
+    }
+}
+
+/// Reads a dtype such as `torch.float16`, returning its safetensors dtype and element size.
+fn get_dtype(value: &Value) -> anyhow::Result<(&'static str, u64)> {
+    let Value::Global(name) = value else {
+        return Err(anyhow!("tensor has a malformed dtype"));
+    };
+    TENSOR_DTYPES
+        .iter()
+        .find(|(torch_dtype, ..)| torch_dtype == name)
+        .map(|(_, dtype, element_size)| (*dtype, *element_size))
+        .ok_or(anyhow!("unsupported dtype {name}"))
+}
+
+/// Records where a converted file came from, including the checkpoint's SHA256 so it can still be
+/// matched against Civitai.
+fn get_metadata(path: &Path, skipped_keys: &[String]) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut metadata = BTreeMap::new();
+    metadata.insert("format".to_string(), "pt".to_string());
+    metadata.insert(
+        "converted_by".to_string(),
+        format!("civitdl {}", env!("CARGO_PKG_VERSION")),
+    );
+    metadata.insert(
+        "converted_from".to_string(),
+        path.file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default(),
+    );
+    metadata.insert(
+        "converted_from_sha256".to_string(),
+        hash_file(path, HashKind::Sha256)?.to_uppercase(),
+    );
+    metadata.insert(
+        "converted_at".to_string(),
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+            .to_string(),
+    );
+    if !skipped_keys.is_empty() {
+        metadata.insert("converted_skipped_keys".to_string(), skipped_keys.join(","));
+    }
+    Ok(metadata)
+}
+
+fn write_safetensors(
+    path: &Path,
+    archive: &mut zip::ZipArchive<File>,
+    prefix: &str,
+    tensors: &BTreeMap<String, TensorRef>,
+    infos: &BTreeMap<String, TensorInfo>,
+    metadata: &BTreeMap<String, String>,
+) -> anyhow::Result<()> {
+    let file =
+        File::create(path).with_context(|| format!("Failed to create '{}'", path.to_string_lossy()))?;
+    let mut writer = BufWriter::new(file);
+    safetensors::write_header(&mut writer, infos, metadata)?;
+
+    // Tensors are written in name order, so a storage shared by tensors that aren't adjacent is
+    // read again; typically every tensor has its own.
+    let mut storage = Vec::new();
+    let mut storage_key = None;
+    for (name, tensor) in tensors {
+        if storage_key.as_ref() != Some(&tensor.storage_key) {
+            storage.clear();
+            archive
+                .by_name(&format!("{prefix}data/{}", tensor.storage_key))
+                .with_context(|| format!("Storage {} of '{name}' is missing", tensor.storage_key))?
+                .read_to_end(&mut storage)?;
+            storage_key = Some(tensor.storage_key.clone());
+        }
+        let data = tensor
+            .read(&storage)
+            .with_context(|| format!("Failed to read tensor '{name}'"))?;
+        writer.write_all(&data)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::write::SimpleFileOptions;
+
+    use super::*;
+    use crate::pickle::tests::get_state_dict_pickle;
+
+    /// Six little-endian F32s, 0.0 to 5.0, as a 3x2 row-major matrix.
+    fn get_storage() -> Vec<u8> {
+        (0..6).flat_map(|i| (i as f32).to_le_bytes()).collect()
+    }
+
+    fn to_f32s(bytes: &[u8]) -> Vec<f32> {
+        bytes
+            .chunks(4)
+            .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+            .collect()
+    }
+
+    fn view(offset: u64, shape: &[u64], stride: &[u64]) -> TensorRef {
+        TensorRef {
+            storage_key: "0".to_string(),
+            dtype: "F32",
+            element_size: 4,
+            offset,
+            shape: shape.to_vec(),
+            stride: stride.to_vec(),
+        }
+    }
+
+    #[test]
+    fn reads_contiguous_view() {
+        let tensor = view(0, &[3, 2], &[2, 1]);
+        assert!(tensor.is_contiguous());
+        assert_eq!(tensor.read(&get_storage()).unwrap(), get_storage());
+
+        let tensor = view(4, &[1, 2], &[7, 1]);
+        assert!(tensor.is_contiguous());
+        assert_eq!(to_f32s(&tensor.read(&get_storage()).unwrap()), [4.0, 5.0]);
+    }
+
+    #[test]
+    fn reads_transposed_view_in_row_major_order() {
+        let tensor = view(0, &[2, 3], &[1, 2]);
+        assert!(!tensor.is_contiguous());
+        let expected = [0.0, 2.0, 4.0, 1.0, 3.0, 5.0]
+            .iter()
+            .flat_map(|f: &f32| f.to_le_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(tensor.read(&get_storage()).unwrap(), expected);
+    }
+
+    #[test]
+    fn reads_strided_view_with_offset() {
+        let tensor = view(1, &[3], &[2]);
+        assert!(!tensor.is_contiguous());
+        assert_eq!(to_f32s(&tensor.read(&get_storage()).unwrap()), [1.0, 3.0, 5.0]);
+    }
+
+    #[test]
+    fn rejects_view_past_storage() {
+        assert!(view(2, &[3, 2], &[2, 1]).read(&get_storage()).is_err());
+        assert!(view(0, &[2, 3], &[1, 3]).read(&get_storage()).is_err());
+        assert!(view(9, &[0], &[1]).read(&get_storage()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reads_tensors_of_untyped_storages() {
+        let storage = |storage_type: &str| {
+            Value::PersistentId(Box::new(Value::Tuple(vec![
+                Value::Str("storage".to_string()),
+                Value::Global(storage_type.to_string()),
+                Value::Str("0".to_string()),
+                Value::Str("cpu".to_string()),
+                Value::Int(12),
+            ])))
+        };
+        let rebuild = |global: &str, storage_type: &str, dtype: &str| Value::Call {
+            global: global.to_string(),
+            args: vec![
+                storage(storage_type),
+                Value::Int(0),
+                Value::Tuple(vec![Value::Int(2), Value::Int(3)]),
+                Value::Tuple(vec![Value::Int(3), Value::Int(1)]),
+                Value::Bool(false),
+                Value::Dict(Vec::new()),
+                Value::Global(dtype.to_string()),
+            ],
+        };
+
+        let tensor = get_tensor(&rebuild(
+            "torch._utils._rebuild_tensor_v3",
+            "torch.storage.UntypedStorage",
+            "torch.float16",
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!((tensor.dtype, tensor.element_size), ("F16", 2));
+        assert_eq!(tensor.shape, [2, 3]);
+
+        let tensor = get_tensor(&rebuild(
+            "torch._utils._rebuild_tensor_v3",
+            "torch.UntypedStorage",
+            "torch.float8_e4m3fn",
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!((tensor.dtype, tensor.element_size), ("F8_E4M3", 1));
+
+        // Only v3 says what an untyped storage holds.
+        assert!(get_tensor(&rebuild(
+            "torch._utils._rebuild_tensor_v2",
+            "torch.UntypedStorage",
+            "torch.float16",
+        ))
+        .is_err());
+        assert!(get_tensor(&rebuild(
+            "torch._utils._rebuild_tensor_v3",
+            "torch.UntypedStorage",
+            "torch.complex32",
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn converts_checkpoint() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("model.ckpt");
+        let mut archive = zip::ZipWriter::new(File::create(&path).unwrap());
+        for (name, data) in [
+            ("model/data.pkl", get_state_dict_pickle()),
+            ("model/byteorder", b"little".to_vec()),
+            ("model/data/0", get_storage()),
+        ] {
+            archive.start_file(name, SimpleFileOptions::default()).unwrap();
+            archive.write_all(&data).unwrap();
+        }
+        archive.finish().unwrap();
+
+        let conversion = convert(&path, true, false).unwrap();
+        assert_eq!(conversion.target, directory.path().join("model.safetensors"));
+        assert_eq!(conversion.tensor_count, 1);
+        assert_eq!(conversion.skipped_keys, ["global_step"]);
+        assert!(!path.exists());
+
+        let header = safetensors::read_header(&conversion.target).unwrap();
+        let weight = &header.tensors["weight"];
+        assert_eq!((weight.dtype.as_str(), weight.shape.as_slice()), ("F32", &[2, 3][..]));
+        assert_eq!(header.metadata["converted_from"], "model.ckpt");
+        assert_eq!(header.metadata["converted_skipped_keys"], "global_step");
+        let data = fs::read(&conversion.target).unwrap();
+        let start = 8 + header.header_size as usize;
+        assert_eq!(to_f32s(&data[start..]), [0.0, 2.0, 4.0, 1.0, 3.0, 5.0]);
+    }
+}
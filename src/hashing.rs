@@ -0,0 +1,814 @@
+use std::cmp::min;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use blake3::hazmat::{merge_subtrees_non_root, merge_subtrees_root, ChainingValue, HasherExt, Mode};
+use serde::{Deserialize, Serialize};
+use sha2::digest::generic_array::GenericArray;
+use strum::{AsRefStr, EnumString};
+use tracing::debug;
+
+use crate::model::model_version::Hashes;
+
+const READ_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// AutoV1 is the legacy A1111 model hash: the SHA256 of the 64 KiB starting 1 MiB into the file,
+/// shortened to 8 hex characters.
+const AUTO_V1_OFFSET: u64 = 0x100000;
+const AUTO_V1_LENGTH: u64 = 0x10000;
+const AUTO_V1_HEX_LENGTH: usize = 8;
+const AUTO_V2_HEX_LENGTH: usize = 10;
+
+const SHA256_BLOCK_SIZE: usize = 64;
+const SHA256_INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// BLAKE3 input is hashed as subtrees of this many bytes, whose chaining values can be saved and
+/// merged later.
+pub const BLAKE3_BLOCK_SIZE: u64 = 1024 * 1024;
+
+/// The hash algorithms Civitai reports for a file, strongest first.
+#[derive(AsRefStr, Debug, Clone, Copy, EnumString, PartialEq, Eq)]
+pub enum HashKind {
+    #[strum(serialize = "SHA256")]
+    Sha256,
+    #[strum(serialize = "BLAKE3")]
+    Blake3,
+    AutoV2,
+    #[strum(serialize = "CRC32")]
+    Crc32,
+    AutoV1,
+}
+
+impl HashKind {
+    pub const STRONGEST_FIRST: [HashKind; 5] = [
+        HashKind::Sha256,
+        HashKind::Blake3,
+        HashKind::AutoV2,
+        HashKind::Crc32,
+        HashKind::AutoV1,
+    ];
+
+    /// Returns the value Civitai reported for this kind of hash, if any.
+    pub fn expected_from(&self, hashes: &Hashes) -> Option<String> {
+        let value = match self {
+            HashKind::Sha256 => hashes.sha256.clone(),
+            HashKind::Blake3 => hashes.blake3.clone(),
+            HashKind::AutoV2 => hashes.auto_v2.clone(),
+            HashKind::Crc32 => hashes.crc32.clone(),
+            HashKind::AutoV1 => hashes.auto_v1.clone(),
+        };
+        value.filter(|v| !v.trim().is_empty())
+    }
+}
+
+/// Picks the strongest hash Civitai reported that we know how to compute.
+pub fn get_strongest_expected_hash(hashes: &Hashes) -> Option<(HashKind, String)> {
+    HashKind::STRONGEST_FIRST
+        .iter()
+        .find_map(|kind| kind.expected_from(hashes).map(|v| (*kind, v)))
+}
+
+/// Returned (wrapped in an [`anyhow::Error`]) whenever a file on disk does not match the hash
+/// Civitai reported for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashMismatch {
+    pub path: PathBuf,
+    pub kind: HashKind,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for HashMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} hash mismatch for '{}': expected {}, got {}",
+            self.kind.as_ref(),
+            self.path.to_string_lossy(),
+            self.expected,
+            self.actual
+        )
+    }
+}
+
+impl std::error::Error for HashMismatch {}
+
+/// Hashes computed over a file, as lowercase hex.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ComputedHashes {
+    pub sha256: Option<String>,
+    pub blake3: Option<String>,
+    pub crc32: Option<String>,
+    pub auto_v1: Option<String>,
+}
+
+impl ComputedHashes {
+    /// Whether no hash was computed at all.
+    pub fn is_empty(&self) -> bool {
+        *self == ComputedHashes::default()
+    }
+
+    pub fn get(&self, kind: HashKind) -> Option<String> {
+        match kind {
+            HashKind::Sha256 => self.sha256.clone(),
+            HashKind::Blake3 => self.blake3.clone(),
+            HashKind::AutoV2 => self
+                .sha256
+                .as_ref()
+                .map(|h| h[..AUTO_V2_HEX_LENGTH].to_string()),
+            HashKind::Crc32 => self.crc32.clone(),
+            HashKind::AutoV1 => self.auto_v1.clone(),
+        }
+    }
+
+    /// Whether `hash` is any of these hashes, as found in infotext `Model hash` values or
+    /// `Resource.hash`.
+    pub fn matches(&self, hash: &str) -> bool {
+        let hash = hash.trim();
+        !hash.is_empty()
+            && HashKind::STRONGEST_FIRST
+                .iter()
+                .filter_map(|kind| self.get(*kind))
+                .any(|h| h.eq_ignore_ascii_case(hash))
+    }
+
+    /// Compares these hashes against the strongest hash in `hashes` that was also computed.
+    ///
+    /// Returns `Ok(false)` if there is nothing to compare, and a [`HashMismatch`] error if the
+    /// hashes differ.
+    pub fn verify(&self, path: &Path, hashes: &Hashes) -> anyhow::Result<bool> {
+        let Some((kind, expected, actual)) = HashKind::STRONGEST_FIRST.iter().find_map(|kind| {
+            Some((*kind, kind.expected_from(hashes)?, self.get(*kind)?))
+        }) else {
+            debug!(path =? path, "No usable hashes available for verification");
+            return Ok(false);
+        };
+        if actual.eq_ignore_ascii_case(expected.trim()) {
+            debug!(path =? path, kind =? kind, "Hash verified");
+            Ok(true)
+        } else {
+            Err(HashMismatch {
+                path: path.to_path_buf(),
+                kind,
+                expected: expected.trim().to_lowercase(),
+                actual,
+            }
+            .into())
+        }
+    }
+}
+
+impl From<ComputedHashes> for Hashes {
+    /// Converts to the uppercase hex Civitai uses.
+    fn from(computed: ComputedHashes) -> Self {
+        let upper = |kind| computed.get(kind).map(|h| h.to_uppercase());
+        Hashes {
+            auto_v1: upper(HashKind::AutoV1),
+            auto_v2: upper(HashKind::AutoV2),
+            sha256: upper(HashKind::Sha256),
+            crc32: upper(HashKind::Crc32),
+            blake3: upper(HashKind::Blake3),
+        }
+    }
+}
+
+/// SHA256 whose state can be saved, so hashing can pick up where an earlier run left off.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Sha256State {
+    state: [u32; 8],
+    /// Input that doesn't fill a block yet.
+    pending: Vec<u8>,
+    length: u64,
+}
+
+impl Sha256State {
+    fn new() -> Self {
+        Sha256State {
+            state: SHA256_INITIAL_STATE,
+            pending: Vec::with_capacity(SHA256_BLOCK_SIZE),
+            length: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        if !self.pending.is_empty() {
+            let take = min(SHA256_BLOCK_SIZE - self.pending.len(), data.len());
+            self.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.pending.len() < SHA256_BLOCK_SIZE {
+                return;
+            }
+            sha2::compress256(&mut self.state, &[GenericArray::clone_from_slice(&self.pending)]);
+            self.pending.clear();
+        }
+        let blocks = data.chunks_exact(SHA256_BLOCK_SIZE);
+        let rest = blocks.remainder();
+        for block in blocks {
+            sha2::compress256(&mut self.state, std::slice::from_ref(GenericArray::from_slice(block)));
+        }
+        self.pending.extend_from_slice(rest);
+    }
+
+    fn finalize(mut self) -> String {
+        let bits = self.length * 8;
+        let mut tail = std::mem::take(&mut self.pending);
+        tail.push(0x80);
+        while tail.len() % SHA256_BLOCK_SIZE != SHA256_BLOCK_SIZE - 8 {
+            tail.push(0);
+        }
+        tail.extend_from_slice(&bits.to_be_bytes());
+        for block in tail.chunks_exact(SHA256_BLOCK_SIZE) {
+            sha2::compress256(&mut self.state, std::slice::from_ref(GenericArray::from_slice(block)));
+        }
+        self.state.iter().map(|word| format!("{word:08x}")).collect()
+    }
+}
+
+/// BLAKE3 over [`BLAKE3_BLOCK_SIZE`] subtrees, so the chaining values of complete blocks can be
+/// saved and merged later.
+#[derive(Clone)]
+struct Blake3Tree {
+    /// Where the first block starts in the whole input.
+    start: u64,
+    /// Chaining values of the blocks before `current`.
+    blocks: Vec<ChainingValue>,
+    current: blake3::Hasher,
+    current_len: u64,
+}
+
+impl Blake3Tree {
+    fn new() -> Self {
+        Blake3Tree::from_blocks(0, Vec::new())
+    }
+
+    /// Picks up hashing after the given complete blocks, the first of which starts at `start`.
+    fn from_blocks(start: u64, blocks: Vec<ChainingValue>) -> Self {
+        let mut current = blake3::Hasher::new();
+        current.set_input_offset(start + blocks.len() as u64 * BLAKE3_BLOCK_SIZE);
+        Blake3Tree {
+            start,
+            blocks,
+            current,
+            current_len: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.current_len == BLAKE3_BLOCK_SIZE {
+                // Blocks are only closed once more input arrives, since a single block is the
+                // root itself.
+                let mut blocks = std::mem::take(&mut self.blocks);
+                blocks.push(self.current.finalize_non_root());
+                *self = Blake3Tree::from_blocks(self.start, blocks);
+            }
+            let take = min(BLAKE3_BLOCK_SIZE - self.current_len, data.len() as u64) as usize;
+            self.current.update(&data[..take]);
+            self.current_len += take as u64;
+            data = &data[take..];
+        }
+    }
+
+    /// Returns the chaining values of every block, or `None` while the last block is incomplete.
+    fn complete_blocks(&self) -> Option<Vec<ChainingValue>> {
+        let mut blocks = self.blocks.clone();
+        match self.current_len {
+            0 => {}
+            BLAKE3_BLOCK_SIZE => blocks.push(self.current.finalize_non_root()),
+            _ => return None,
+        }
+        Some(blocks)
+    }
+
+    fn finalize(&self) -> blake3::Hash {
+        if self.blocks.is_empty() {
+            return self.current.finalize();
+        }
+        let mut blocks = self.blocks.clone();
+        if self.current_len > 0 {
+            blocks.push(self.current.finalize_non_root());
+        }
+        merge_blake3_root(&blocks)
+    }
+}
+
+/// Computes the BLAKE3 chaining values of one segment of a file as it is downloaded, so segments
+/// fetched in parallel never need to be read back to be hashed. Combine them with
+/// [`combine_blake3_segments`].
+#[derive(Clone)]
+pub struct Blake3Segment(Blake3Tree);
+
+impl Blake3Segment {
+    /// Starts a segment at byte `start` of the file, which must be a multiple of
+    /// [`BLAKE3_BLOCK_SIZE`].
+    pub fn new(start: u64) -> Self {
+        Blake3Segment(Blake3Tree::from_blocks(start, Vec::new()))
+    }
+
+    /// Picks up a segment starting at byte `start` after the given complete blocks, as returned
+    /// by [`Blake3Segment::blocks`].
+    pub fn resume(start: u64, blocks: Vec<ChainingValue>) -> Self {
+        Blake3Segment(Blake3Tree::from_blocks(start, blocks))
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    /// Returns the chaining values of the blocks that are known to be complete so far. The block
+    /// being fed is only counted once data past it arrives or the segment is finished.
+    pub fn blocks(&self) -> &[ChainingValue] {
+        &self.0.blocks
+    }
+
+    /// Returns the chaining values of the blocks of the segment. Only the last segment of a file
+    /// may end part way through a block.
+    pub fn finish(self) -> Vec<ChainingValue> {
+        let mut blocks = self.0.blocks;
+        if self.0.current_len > 0 {
+            blocks.push(self.0.current.finalize_non_root());
+        }
+        blocks
+    }
+}
+
+/// Computes the BLAKE3 hash of a file from the blocks of all its segments, in file order, as
+/// lowercase hex. Returns `None` for files of a single block, which have to be hashed whole.
+pub fn combine_blake3_segments(segments: Vec<Vec<ChainingValue>>) -> Option<String> {
+    let blocks = segments.concat();
+    (blocks.len() > 1).then(|| merge_blake3_root(&blocks).to_hex().to_string())
+}
+
+/// Merges the chaining values of consecutive [`BLAKE3_BLOCK_SIZE`] blocks into the BLAKE3 hash of
+/// the whole input. Only the last block may be shorter than a full block, and there must be at
+/// least two.
+fn merge_blake3_root(blocks: &[ChainingValue]) -> blake3::Hash {
+    let (left, right) = blocks.split_at(get_left_subtree_blocks(blocks.len()));
+    merge_subtrees_root(&merge_blake3_blocks(left), &merge_blake3_blocks(right), Mode::Hash)
+}
+
+fn merge_blake3_blocks(blocks: &[ChainingValue]) -> ChainingValue {
+    if let [block] = blocks {
+        return *block;
+    }
+    let (left, right) = blocks.split_at(get_left_subtree_blocks(blocks.len()));
+    merge_subtrees_non_root(&merge_blake3_blocks(left), &merge_blake3_blocks(right), Mode::Hash)
+}
+
+/// BLAKE3 puts the largest power of two of the blocks that leaves at least one behind in the left
+/// subtree.
+fn get_left_subtree_blocks(blocks: usize) -> usize {
+    1 << (blocks - 1).ilog2()
+}
+
+/// Where a [`StreamingHasher`] was part way through its input, saved so hashing can pick up
+/// from there instead of reading everything again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HasherState {
+    pub position: u64,
+    sha256: Option<Sha256State>,
+    /// Hex chaining values of the complete [`BLAKE3_BLOCK_SIZE`] blocks.
+    blake3: Option<Vec<String>>,
+    crc32: Option<u32>,
+    auto_v1: Option<Sha256State>,
+}
+
+impl HasherState {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read(path)
+            .with_context(|| format!("Failed to read '{}'", path.to_string_lossy()))?;
+        serde_json::from_slice(&contents)
+            .with_context(|| format!("Failed to parse '{}'", path.to_string_lossy()))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_vec(self)?)
+            .with_context(|| format!("Failed to write '{}'", path.to_string_lossy()))
+    }
+}
+
+/// Incrementally hashes data as it is written so large files never need to be read back.
+///
+/// Data must be fed in file order, starting from the beginning of the file, for AutoV1 to pick
+/// the right slice.
+#[derive(Clone)]
+pub struct StreamingHasher {
+    sha256: Option<Sha256State>,
+    blake3: Option<Blake3Tree>,
+    crc32: Option<crc32fast::Hasher>,
+    auto_v1: Option<Sha256State>,
+    position: u64,
+}
+
+impl StreamingHasher {
+    pub fn new(kinds: &[HashKind]) -> Self {
+        let sha256 = kinds
+            .iter()
+            .any(|k| matches!(k, HashKind::Sha256 | HashKind::AutoV2))
+            .then(Sha256State::new);
+        let blake3 = kinds.contains(&HashKind::Blake3).then(Blake3Tree::new);
+        let crc32 = kinds.contains(&HashKind::Crc32).then(crc32fast::Hasher::new);
+        let auto_v1 = kinds.contains(&HashKind::AutoV1).then(Sha256State::new);
+        StreamingHasher {
+            sha256,
+            blake3,
+            crc32,
+            auto_v1,
+            position: 0,
+        }
+    }
+
+    /// Creates a hasher for a download: SHA256 is always computed, the others only when Civitai
+    /// reported them.
+    pub fn for_expected(hashes: Option<&Hashes>) -> Self {
+        let mut kinds = vec![HashKind::Sha256];
+        if let Some(h) = hashes {
+            kinds.extend(
+                [HashKind::Blake3, HashKind::Crc32, HashKind::AutoV1]
+                    .into_iter()
+                    .filter(|k| k.expected_from(h).is_some()),
+            );
+        }
+        StreamingHasher::new(&kinds)
+    }
+
+    /// The number of bytes hashed so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        if let Some(h) = self.sha256.as_mut() {
+            h.update(chunk);
+        }
+        if let Some(h) = self.blake3.as_mut() {
+            h.update(chunk);
+        }
+        if let Some(h) = self.crc32.as_mut() {
+            h.update(chunk);
+        }
+        if let Some(h) = self.auto_v1.as_mut() {
+            let end = self.position + chunk.len() as u64;
+            let from = AUTO_V1_OFFSET.clamp(self.position, end);
+            let to = (AUTO_V1_OFFSET + AUTO_V1_LENGTH).clamp(self.position, end);
+            h.update(&chunk[(from - self.position) as usize..(to - self.position) as usize]);
+        }
+        self.position += chunk.len() as u64;
+    }
+
+    /// Feeds the contents of the file at `path` from the current position on into the hasher,
+    /// returning the number of bytes read.
+    pub fn update_from_file(&mut self, path: &Path) -> anyhow::Result<u64> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(self.position))?;
+        let mut buffer = vec![0; READ_BUFFER_SIZE];
+        let mut total = 0;
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            self.update(&buffer[..read]);
+            total += read as u64;
+        }
+        Ok(total)
+    }
+
+    /// Saves where the hasher is. Returns `None` when BLAKE3 is part way through a block, or
+    /// hasn't got past the first one yet, since those can't be picked up again.
+    pub fn save_state(&self) -> Option<HasherState> {
+        let blake3 = match &self.blake3 {
+            Some(tree) => {
+                let blocks = tree.complete_blocks().filter(|b| b.len() > 1)?;
+                Some(blocks.iter().map(|cv| blake3::Hash::from(*cv).to_hex().to_string()).collect())
+            }
+            None => None,
+        };
+        Some(HasherState {
+            position: self.position,
+            sha256: self.sha256.clone(),
+            blake3,
+            crc32: self.crc32.as_ref().map(|h| h.clone().finalize()),
+            auto_v1: self.auto_v1.clone(),
+        })
+    }
+
+    /// Picks up from `state` instead of starting over. The hasher must not have been fed
+    /// anything yet, and `state` must have been saved by a hasher computing the same hashes.
+    pub fn resume(self, state: HasherState) -> anyhow::Result<Self> {
+        if self.position != 0 {
+            return Err(anyhow!("Hasher has already been fed {} bytes", self.position));
+        }
+        let same_kinds = self.sha256.is_some() == state.sha256.is_some()
+            && self.blake3.is_some() == state.blake3.is_some()
+            && self.crc32.is_some() == state.crc32.is_some()
+            && self.auto_v1.is_some() == state.auto_v1.is_some();
+        if !same_kinds {
+            return Err(anyhow!("Saved hasher state is for different hashes"));
+        }
+        let blake3 = match state.blake3 {
+            Some(blocks) => {
+                let blocks = blocks
+                    .iter()
+                    .map(|cv| Ok(*blake3::Hash::from_hex(cv)?.as_bytes()))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                if blocks.len() < 2 || blocks.len() as u64 * BLAKE3_BLOCK_SIZE != state.position {
+                    return Err(anyhow!("Saved BLAKE3 state does not match its position"));
+                }
+                Some(Blake3Tree::from_blocks(0, blocks))
+            }
+            None => None,
+        };
+        let lengths_match = [&state.sha256, &state.auto_v1]
+            .into_iter()
+            .flatten()
+            .all(|h| h.pending.len() < SHA256_BLOCK_SIZE)
+            && state.sha256.as_ref().is_none_or(|h| h.length == state.position);
+        if !lengths_match {
+            return Err(anyhow!("Saved SHA256 state does not match its position"));
+        }
+        Ok(StreamingHasher {
+            sha256: state.sha256,
+            blake3,
+            crc32: state
+                .crc32
+                .map(|crc| crc32fast::Hasher::new_with_initial_len(crc, state.position)),
+            auto_v1: state.auto_v1,
+            position: state.position,
+        })
+    }
+
+    pub fn finalize(self) -> ComputedHashes {
+        ComputedHashes {
+            sha256: self.sha256.map(Sha256State::finalize),
+            blake3: self.blake3.map(|h| h.finalize().to_hex().to_string()),
+            crc32: self.crc32.map(|h| format!("{:08x}", h.finalize())),
+            auto_v1: self
+                .auto_v1
+                .map(|h| h.finalize()[..AUTO_V1_HEX_LENGTH].to_string()),
+        }
+    }
+}
+
+/// Computes the given kind of hash over the file at `path` as lowercase hex.
+#[tracing::instrument(level = "debug")]
+pub fn hash_file(path: &Path, kind: HashKind) -> anyhow::Result<String> {
+    let mut hasher = StreamingHasher::new(&[kind]);
+    hasher.update_from_file(path)?;
+    let hash = hasher
+        .finalize()
+        .get(kind)
+        .expect("hasher was created for this kind");
+    debug!(path =? path, kind =? kind, hash =? &hash, "Computed hash");
+    Ok(hash)
+}
+
+/// Computes every kind of hash Civitai reports over the file at `path` in a single pass.
+#[tracing::instrument(level = "debug")]
+pub fn hash_file_all(path: &Path) -> anyhow::Result<ComputedHashes> {
+    let mut hasher = StreamingHasher::new(&HashKind::STRONGEST_FIRST);
+    hasher.update_from_file(path)?;
+    Ok(hasher.finalize())
+}
+
+/// Hashes the file at `path` with the strongest algorithm available in `hashes` and compares it
+/// against the expected value.
+///
+/// Returns `Ok(false)` if Civitai did not report any hash we can check, and a [`HashMismatch`]
+/// error if the file does not match.
+pub fn verify_file(path: &Path, hashes: &Hashes) -> anyhow::Result<bool> {
+    Ok(hash_and_verify_file(path, hashes)?.is_some())
+}
+
+/// Like [`verify_file`], but returns the hashes that were computed when the file matches.
+pub fn hash_and_verify_file(path: &Path, hashes: &Hashes) -> anyhow::Result<Option<ComputedHashes>> {
+    let Some((kind, _)) = get_strongest_expected_hash(hashes) else {
+        debug!(path =? path, "No usable hashes available for verification");
+        return Ok(None);
+    };
+    let mut hasher = StreamingHasher::new(&[kind]);
+    hasher.update_from_file(path)?;
+    let computed = hasher.finalize();
+    Ok(computed.verify(path, hashes)?.then_some(computed))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    // Expected values come from A1111's `model_hash` (AutoV1), `hashlib.sha256` (AutoV2) and
+    // `zlib.crc32` over the same bytes.
+    const LONG_SHA256: &str = "e027f527e307aaa32b1f3a7bd9ffda6d6c97de74f138df8e387b1cc4649c2c50";
+    const LONG_AUTO_V1: &str = "31fff905";
+    const LONG_CRC32: &str = "900fc85f";
+    const PARTIAL_AUTO_V1: &str = "e13f1602";
+    const SHORT_SHA256: &str = "59425e4412e296fc74736673ce067027f384203f59c0d2c3e6be7b13347b3ffc";
+    const SHORT_CRC32: &str = "04da8651";
+    /// A1111 hashes the empty slice it reads past the end of short files.
+    const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    fn get_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| ((i * 7 + (i >> 12)) % 251) as u8).collect()
+    }
+
+    fn hash_all(data: &[u8]) -> ComputedHashes {
+        let mut hasher = StreamingHasher::new(&HashKind::STRONGEST_FIRST);
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    #[test]
+    fn matches_a1111_past_auto_v1_slice() {
+        let hashes = hash_all(&get_data(0x120000));
+        assert_eq!(hashes.get(HashKind::Sha256).unwrap(), LONG_SHA256);
+        assert_eq!(hashes.get(HashKind::AutoV2).unwrap(), LONG_SHA256[..10]);
+        assert_eq!(hashes.get(HashKind::AutoV1).unwrap(), LONG_AUTO_V1);
+        assert_eq!(hashes.get(HashKind::Crc32).unwrap(), LONG_CRC32);
+    }
+
+    #[test]
+    fn matches_a1111_within_auto_v1_slice() {
+        let hashes = hash_all(&get_data(0x100000 + 100));
+        assert_eq!(hashes.get(HashKind::AutoV1).unwrap(), PARTIAL_AUTO_V1);
+    }
+
+    #[test]
+    fn matches_a1111_before_auto_v1_slice() {
+        let hashes = hash_all(&get_data(1000));
+        assert_eq!(hashes.get(HashKind::Sha256).unwrap(), SHORT_SHA256);
+        assert_eq!(hashes.get(HashKind::AutoV2).unwrap(), SHORT_SHA256[..10]);
+        assert_eq!(hashes.get(HashKind::AutoV1).unwrap(), EMPTY_SHA256[..8]);
+        assert_eq!(hashes.get(HashKind::Crc32).unwrap(), SHORT_CRC32);
+    }
+
+    #[test]
+    fn matches_blake3_test_vectors() {
+        assert_eq!(
+            hash_all(&[]).get(HashKind::Blake3).unwrap(),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+        let data = (0..1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        assert_eq!(
+            hash_all(&data).get(HashKind::Blake3).unwrap(),
+            "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7"
+        );
+    }
+
+    #[test]
+    fn chunking_does_not_change_hashes() {
+        let data = get_data(0x120000);
+        let mut hasher = StreamingHasher::new(&HashKind::STRONGEST_FIRST);
+        for chunk in data.chunks(0x10000 - 3) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finalize(), hash_all(&data));
+    }
+
+    #[test]
+    fn matches_blake3_across_blocks() {
+        for len in [BLAKE3_BLOCK_SIZE, 2 * BLAKE3_BLOCK_SIZE, 3 * BLAKE3_BLOCK_SIZE + 5] {
+            let data = get_data(len as usize);
+            assert_eq!(
+                hash_all(&data).get(HashKind::Blake3).unwrap(),
+                blake3::hash(&data).to_hex().to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn resumes_from_saved_state() {
+        let data = get_data(5 * BLAKE3_BLOCK_SIZE as usize + 17);
+        let position = 2 * BLAKE3_BLOCK_SIZE as usize;
+        let mut hasher = StreamingHasher::new(&HashKind::STRONGEST_FIRST);
+        hasher.update(&data[..position - 3]);
+        hasher.update(&data[position - 3..position]);
+        let state = hasher.save_state().unwrap();
+        assert_eq!(state.position, position as u64);
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        state.save(file.path()).unwrap();
+        let mut resumed = StreamingHasher::new(&HashKind::STRONGEST_FIRST)
+            .resume(HasherState::load(file.path()).unwrap())
+            .unwrap();
+        resumed.update(&data[position..]);
+        assert_eq!(resumed.finalize(), hash_all(&data));
+    }
+
+    #[test]
+    fn saves_blake3_state_only_at_block_boundaries() {
+        let data = get_data(2 * BLAKE3_BLOCK_SIZE as usize + 1);
+        let mut hasher = StreamingHasher::new(&[HashKind::Blake3]);
+        hasher.update(&data[..BLAKE3_BLOCK_SIZE as usize]);
+        assert!(hasher.save_state().is_none());
+        hasher.update(&data[BLAKE3_BLOCK_SIZE as usize..]);
+        assert!(hasher.save_state().is_none());
+
+        let mut hasher = StreamingHasher::new(&[HashKind::Sha256]);
+        hasher.update(&data[..1000]);
+        assert!(hasher.save_state().is_some());
+    }
+
+    #[test]
+    fn refuses_state_for_other_hashes() {
+        let mut hasher = StreamingHasher::new(&[HashKind::Sha256]);
+        hasher.update(&get_data(1000));
+        let state = hasher.save_state().unwrap();
+        assert!(StreamingHasher::new(&[HashKind::Sha256, HashKind::Crc32])
+            .resume(state.clone())
+            .is_err());
+
+        let mut hasher = StreamingHasher::new(&[HashKind::Sha256]);
+        hasher.update(b"x");
+        assert!(hasher.resume(state).is_err());
+    }
+
+    #[test]
+    fn combines_blake3_segments() {
+        let data = get_data(5 * BLAKE3_BLOCK_SIZE as usize + 17);
+        let boundaries = [0, 2 * BLAKE3_BLOCK_SIZE as usize, 3 * BLAKE3_BLOCK_SIZE as usize, data.len()];
+        let segments = boundaries
+            .windows(2)
+            .map(|range| {
+                let mut segment = Blake3Segment::new(range[0] as u64);
+                for chunk in data[range[0]..range[1]].chunks(0x10000 - 3) {
+                    segment.update(chunk);
+                }
+                segment.finish()
+            })
+            .collect();
+        assert_eq!(
+            combine_blake3_segments(segments).unwrap(),
+            blake3::hash(&data).to_hex().to_string()
+        );
+
+        let mut segment = Blake3Segment::new(0);
+        segment.update(&data[..1000]);
+        assert!(combine_blake3_segments(vec![segment.finish()]).is_none());
+    }
+
+    #[test]
+    fn resumes_blake3_segments() {
+        let data = get_data(3 * BLAKE3_BLOCK_SIZE as usize + 17);
+        let mut segment = Blake3Segment::new(0);
+        segment.update(&data[..2 * BLAKE3_BLOCK_SIZE as usize + 5]);
+        let blocks = segment.blocks().to_vec();
+        assert_eq!(blocks.len(), 2);
+
+        // Everything past the complete blocks is fed again.
+        let mut resumed = Blake3Segment::resume(0, blocks);
+        resumed.update(&data[2 * BLAKE3_BLOCK_SIZE as usize..]);
+        assert_eq!(
+            combine_blake3_segments(vec![resumed.finish()]).unwrap(),
+            blake3::hash(&data).to_hex().to_string()
+        );
+    }
+
+    #[test]
+    fn hashes_files() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&get_data(0x120000)).unwrap();
+        let hashes = hash_file_all(file.path()).unwrap();
+        assert_eq!(hashes.sha256.as_deref(), Some(LONG_SHA256));
+        assert_eq!(hash_file(file.path(), HashKind::AutoV1).unwrap(), LONG_AUTO_V1);
+        assert_eq!(hash_file(file.path(), HashKind::Crc32).unwrap(), LONG_CRC32);
+    }
+
+    #[test]
+    fn verifies_strongest_reported_hash() {
+        let hashes = hash_all(&get_data(0x120000));
+        let path = Path::new("model.safetensors");
+        let reported = Hashes {
+            auto_v1: Some("00000000".to_string()),
+            sha256: Some(LONG_SHA256.to_uppercase()),
+            ..Default::default()
+        };
+        assert!(hashes.verify(path, &reported).unwrap());
+
+        let reported = Hashes {
+            crc32: Some("DEADBEEF".to_string()),
+            ..Default::default()
+        };
+        let error = hashes.verify(path, &reported).unwrap_err();
+        let mismatch = error.downcast_ref::<HashMismatch>().unwrap();
+        assert_eq!(mismatch.kind, HashKind::Crc32);
+        assert_eq!(mismatch.actual, LONG_CRC32);
+
+        assert!(!hashes.verify(path, &Hashes::default()).unwrap());
+    }
+
+    #[test]
+    fn converts_to_uppercase_hashes() {
+        let hashes = Hashes::from(hash_all(&get_data(0x120000)));
+        assert_eq!(hashes.auto_v1.as_deref(), Some("31FFF905"));
+        assert_eq!(hashes.auto_v2, Some(LONG_SHA256[..10].to_uppercase()));
+        assert_eq!(hashes.crc32.as_deref(), Some("900FC85F"));
+    }
+}
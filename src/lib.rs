@@ -1,24 +1,63 @@
-#![feature(result_option_inspect)]
-#![feature(const_option)]
-#![feature(unwrap_infallible)]
-
-use reqwest::{cookie::Jar, Url};
+use reqwest::header::{
+    HeaderMap, ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_RANGE, CONTENT_TYPE, RANGE,
+};
+use reqwest::{cookie::Jar, Response, StatusCode, Url};
+pub mod content_disposition;
+pub mod convert;
+pub mod hashing;
+pub mod manifest;
 pub mod model;
-use anyhow::anyhow;
-use futures::{future::join_all, StreamExt};
+pub mod pickle;
+pub mod plan;
+pub mod preflight;
+pub mod preview;
+pub mod report;
+pub mod retry;
+pub mod safetensors;
+pub mod scan;
+pub mod scheduler;
+pub mod segments;
+pub mod sidecar;
+pub mod throttle;
+pub mod update;
+use anyhow::{anyhow, Context};
+use blake3::hazmat::ChainingValue;
+use futures::{
+    future::join_all,
+    stream, StreamExt, TryStreamExt,
+};
+use hashing::{
+    Blake3Segment, ComputedHashes, HashKind, HashMismatch, HasherState, StreamingHasher,
+    BLAKE3_BLOCK_SIZE,
+};
+use manifest::{Manifest, ManifestEntry};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use model::model_version::ModelVersion;
+use model::model_version::{ModelVersion, NSFW};
 use model::model_version::ResourceFile;
 use model::Model;
 use normpath::{self, PathExt};
-use serde::{Deserialize, Serialize};
+use pickle::PickleReport;
+use plan::{PlannedAction, PlannedItem};
+use preflight::PlannedDownload;
+use report::{DownloadOutcome, DownloadReport};
+use retry::{check_response, is_transient, RetryPolicy, TransientError};
+use safetensors::InvalidSafetensors;
+use scan::{ScanOutcome, ScanResult};
+use scheduler::Scheduler;
+use segments::SegmentedDownload;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::cmp::min;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::sync::Arc;
-use strum::{AsRefStr, EnumString};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use strum::{AsRefStr, EnumString, EnumVariantNames, VariantNames};
+use throttle::{RateLimiter, Throttle};
+use tokio::sync::OwnedSemaphorePermit;
+use update::AvailableUpdate;
 use tracing::{debug, error, trace, warn};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -29,9 +68,51 @@ pub struct Config {
     token: Option<String>,
     model_format: ModelFormat,
     resource_type: ResourceType,
+    #[serde(default = "default_max_concurrent_downloads")]
+    max_concurrent_downloads: usize,
+    #[serde(default = "default_max_concurrent_requests")]
+    max_concurrent_requests: usize,
+    #[serde(default = "default_retry_max_attempts")]
+    retry_max_attempts: u32,
+    #[serde(default = "default_retry_base_delay_ms")]
+    retry_base_delay_ms: u64,
+    #[serde(default = "default_retry_max_delay_ms")]
+    retry_max_delay_ms: u64,
+    #[serde(default = "default_retry_max_retry_after_ms")]
+    retry_max_retry_after_ms: u64,
+    #[serde(default = "default_retry_jitter")]
+    retry_jitter: bool,
+    #[serde(default = "default_segmented_download_connections")]
+    segmented_download_connections: usize,
+    #[serde(default = "default_segmented_download_threshold_mb")]
+    segmented_download_threshold_mb: u64,
+    #[serde(default = "default_verify_segmented_downloads")]
+    verify_segmented_downloads: bool,
+    max_bytes_per_second: Option<u64>,
+    max_bytes_per_second_per_download: Option<u64>,
+    #[serde(default)]
+    all_files: bool,
+    #[serde(default)]
+    file_types: Vec<String>,
+    #[serde(default)]
+    file_formats: Vec<String>,
+    #[serde(default)]
+    preview_images: usize,
+    #[serde(default)]
+    preview_max_nsfw: NSFW,
+    #[serde(default = "default_write_sidecars")]
+    write_sidecars: bool,
+    manifest_path: Option<PathBuf>,
+    api_base_url: Option<String>,
+    #[serde(default)]
+    allow_failed_scans: bool,
+    #[serde(default)]
+    pickle_allowlist: Vec<String>,
+    quarantine_directory: Option<PathBuf>,
 }
 
-#[derive(AsRefStr, Debug, Serialize, Deserialize, Clone, EnumString, PartialEq, Default)]
+#[derive(AsRefStr, Debug, Serialize, Deserialize, Clone, EnumString, EnumVariantNames, PartialEq, Default)]
+#[strum(ascii_case_insensitive)]
 pub enum ResourceType {
     Model,
     #[strum(serialize = "Pruned Model")]
@@ -41,10 +122,12 @@ pub enum ResourceType {
     TrainingData,
     Archive,
     Config,
+    VAE,
     Unknown
 }
 
-#[derive(AsRefStr, Debug, Serialize, Deserialize, Clone, EnumString, PartialEq, Default)]
+#[derive(AsRefStr, Debug, Serialize, Deserialize, Clone, EnumString, EnumVariantNames, PartialEq, Default)]
+#[strum(ascii_case_insensitive)]
 pub enum ModelFormat {
     #[default]
     SafeTensor,
@@ -53,6 +136,121 @@ pub enum ModelFormat {
     Unknown
 }
 
+/// Which files of a model version get downloaded.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum FileSelection {
+    /// Only the single file best matching the preferred model format and resource type.
+    #[default]
+    Optimal,
+    /// Every file whose type and format are listed. An empty list matches anything.
+    Matching {
+        resource_types: Vec<ResourceType>,
+        model_formats: Vec<ModelFormat>,
+    },
+    /// Every file of the version.
+    All,
+}
+
+/// Parses a [`ResourceType`] or [`ModelFormat`] name, ignoring case, with an error listing the
+/// valid names.
+pub fn parse_variant<T: FromStr + VariantNames>(value: &str) -> anyhow::Result<T> {
+    let value = value.trim();
+    T::from_str(value).map_err(|_| {
+        anyhow!(
+            "Unknown value '{}'. Expected one of: {}",
+            value,
+            T::VARIANTS.join(", ")
+        )
+    })
+}
+
+impl FileSelection {
+    /// Selects the files of the listed types and formats, or the optimal file if both lists are
+    /// empty. Fails on any name that isn't a [`ResourceType`] or [`ModelFormat`].
+    pub fn parse(file_types: &[String], file_formats: &[String]) -> anyhow::Result<Self> {
+        if file_types.is_empty() && file_formats.is_empty() {
+            return Ok(FileSelection::Optimal);
+        }
+        Ok(FileSelection::Matching {
+            resource_types: file_types
+                .iter()
+                .map(|t| parse_variant(t).context("Invalid file type"))
+                .collect::<anyhow::Result<_>>()?,
+            model_formats: file_formats
+                .iter()
+                .map(|f| parse_variant(f).context("Invalid file format"))
+                .collect::<anyhow::Result<_>>()?,
+        })
+    }
+
+    pub fn matches(&self, file: &ResourceFile) -> bool {
+        match self {
+            FileSelection::Optimal => false,
+            FileSelection::All => true,
+            FileSelection::Matching {
+                resource_types,
+                model_formats,
+            } => {
+                let resource_type =
+                    ResourceType::from_str(&file.type_field).unwrap_or(ResourceType::Unknown);
+                let model_format = ModelFormat::from_str(&file.format.clone().unwrap_or_default())
+                    .unwrap_or(ModelFormat::Unknown);
+                (resource_types.is_empty() || resource_types.contains(&resource_type))
+                    && (model_formats.is_empty() || model_formats.contains(&model_format))
+            }
+        }
+    }
+}
+
+/// Why a download was quarantined, kept next to it as `<file>.quarantine.json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct QuarantineRecord {
+    model_id: i64,
+    model_version_id: i64,
+    file_id: i64,
+    source_url: String,
+    concern: String,
+    /// What the pickles import, unless they couldn't be analyzed.
+    report: Option<PickleReport>,
+}
+
+const QUARANTINE_RECORD_SUFFIX: &str = ".quarantine.json";
+
+impl QuarantineRecord {
+    fn write(&self, quarantine_path: &Path) -> anyhow::Result<()> {
+        let mut path = quarantine_path.as_os_str().to_owned();
+        path.push(QUARANTINE_RECORD_SUFFIX);
+        std::fs::write(&path, serde_json::to_vec_pretty(self)?).with_context(|| {
+            format!("Failed to write '{}'", Path::new(&path).to_string_lossy())
+        })
+    }
+
+    /// Finds the quarantined copy of the file `file_id` in `quarantine_directory`, if any.
+    fn find(quarantine_directory: &Path, file_id: i64) -> Option<PathBuf> {
+        std::fs::read_dir(quarantine_directory)
+            .ok()?
+            .flatten()
+            .map(|entry| entry.path())
+            .find_map(|path| {
+                let name = path.to_str()?.strip_suffix(QUARANTINE_RECORD_SUFFIX)?;
+                let record: QuarantineRecord =
+                    serde_json::from_slice(&std::fs::read(&path).ok()?).ok()?;
+                let quarantined = PathBuf::from(name);
+                (record.file_id == file_id && quarantined.exists()).then_some(quarantined)
+            })
+    }
+}
+
+/// A file picked for download, along with the name to save it under when that should not come
+/// from the server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectedFile {
+    pub file: ResourceFile,
+    pub file_name: Option<String>,
+    /// Whether this is the version's main file, which previews and sidecars are named after.
+    pub is_primary: bool,
+}
+
 fn default_stable_diffusion_fallback_directory() -> PathBuf {
     let user_dirs = directories::UserDirs::new().unwrap();
     let downloads_directory = user_dirs.download_dir();
@@ -62,6 +260,50 @@ fn default_stable_diffusion_fallback_directory() -> PathBuf {
         .join("Stable-diffusion")
 }
 
+fn default_max_concurrent_downloads() -> usize {
+    scheduler::DEFAULT_MAX_CONCURRENT_DOWNLOADS
+}
+
+fn default_max_concurrent_requests() -> usize {
+    scheduler::DEFAULT_MAX_CONCURRENT_REQUESTS
+}
+
+fn default_retry_max_attempts() -> u32 {
+    retry::DEFAULT_MAX_ATTEMPTS
+}
+
+fn default_retry_base_delay_ms() -> u64 {
+    retry::DEFAULT_BASE_DELAY_MS
+}
+
+fn default_retry_max_delay_ms() -> u64 {
+    retry::DEFAULT_MAX_DELAY_MS
+}
+
+fn default_retry_max_retry_after_ms() -> u64 {
+    retry::DEFAULT_MAX_RETRY_AFTER_MS
+}
+
+fn default_retry_jitter() -> bool {
+    true
+}
+
+fn default_write_sidecars() -> bool {
+    true
+}
+
+fn default_segmented_download_connections() -> usize {
+    1
+}
+
+fn default_segmented_download_threshold_mb() -> u64 {
+    1024
+}
+
+fn default_verify_segmented_downloads() -> bool {
+    true
+}
+
 pub fn get_config_directory() -> PathBuf {
     let project_dirs = directories::ProjectDirs::from("io", "evanjs", "civitdl").unwrap();
     let config_dir = project_dirs.config_dir();
@@ -101,6 +343,201 @@ impl Config {
             stable_diffusion_fallback_directory: PathBuf::from(stable_diffusion_fallback_directory),
             model_format: ModelFormat::from_str(model_format).unwrap_or_default(),
             resource_type: ResourceType::from_str(resource_type).unwrap_or_default(),
+            max_concurrent_downloads: default_max_concurrent_downloads(),
+            max_concurrent_requests: default_max_concurrent_requests(),
+            retry_max_attempts: default_retry_max_attempts(),
+            retry_base_delay_ms: default_retry_base_delay_ms(),
+            retry_max_delay_ms: default_retry_max_delay_ms(),
+            retry_max_retry_after_ms: default_retry_max_retry_after_ms(),
+            retry_jitter: default_retry_jitter(),
+            segmented_download_connections: default_segmented_download_connections(),
+            segmented_download_threshold_mb: default_segmented_download_threshold_mb(),
+            verify_segmented_downloads: default_verify_segmented_downloads(),
+            max_bytes_per_second: None,
+            max_bytes_per_second_per_download: None,
+            all_files: false,
+            file_types: Vec::new(),
+            file_formats: Vec::new(),
+            preview_images: 0,
+            preview_max_nsfw: NSFW::default(),
+            write_sidecars: default_write_sidecars(),
+            manifest_path: None,
+            api_base_url: None,
+            allow_failed_scans: false,
+            pickle_allowlist: Vec::new(),
+            quarantine_directory: None,
+        }
+    }
+
+    /// Sets how many files may be downloaded at the same time.
+    pub fn with_max_concurrent_downloads(mut self, max_concurrent_downloads: usize) -> Self {
+        self.max_concurrent_downloads = max_concurrent_downloads;
+        self
+    }
+
+    /// Sets how many metadata requests may be in flight at the same time.
+    pub fn with_max_concurrent_requests(mut self, max_concurrent_requests: usize) -> Self {
+        self.max_concurrent_requests = max_concurrent_requests;
+        self
+    }
+
+    /// Sets how failed API requests and downloads are retried.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_max_attempts = retry_policy.max_attempts;
+        self.retry_base_delay_ms = retry_policy.base_delay.as_millis() as u64;
+        self.retry_max_delay_ms = retry_policy.max_delay.as_millis() as u64;
+        self.retry_max_retry_after_ms = retry_policy.max_retry_after.as_millis() as u64;
+        self.retry_jitter = retry_policy.jitter;
+        self
+    }
+
+    /// Sets how many parallel byte ranges large downloads are split into. A single connection
+    /// disables segmented downloads.
+    pub fn with_segmented_download_connections(mut self, connections: usize) -> Self {
+        self.segmented_download_connections = connections;
+        self
+    }
+
+    /// Sets the size above which downloads are split into parallel byte ranges.
+    pub fn with_segmented_download_threshold_mb(mut self, threshold_mb: u64) -> Self {
+        self.segmented_download_threshold_mb = threshold_mb;
+        self
+    }
+
+    /// Sets whether segmented downloads Civitai reported no BLAKE3 hash for are read back once
+    /// they are complete to check their SHA256. Segments are hashed with BLAKE3 as they arrive,
+    /// which is all that can be checked without the extra pass.
+    pub fn with_verify_segmented_downloads(mut self, verify: bool) -> Self {
+        self.verify_segmented_downloads = verify;
+        self
+    }
+
+    /// Caps the combined speed of every download. A cap of 0 means no limit.
+    pub fn with_max_bytes_per_second(mut self, max_bytes_per_second: Option<u64>) -> Self {
+        self.max_bytes_per_second = max_bytes_per_second;
+        self
+    }
+
+    /// Caps the speed of each individual download. A cap of 0 means no limit.
+    pub fn with_max_bytes_per_second_per_download(
+        mut self,
+        max_bytes_per_second_per_download: Option<u64>,
+    ) -> Self {
+        self.max_bytes_per_second_per_download = max_bytes_per_second_per_download;
+        self
+    }
+
+    /// Sets which files of each model version are downloaded.
+    pub fn with_file_selection(mut self, file_selection: FileSelection) -> Self {
+        self.all_files = file_selection == FileSelection::All;
+        (self.file_types, self.file_formats) = match file_selection {
+            FileSelection::Matching {
+                resource_types,
+                model_formats,
+            } => (
+                resource_types.iter().map(|t| t.as_ref().to_string()).collect(),
+                model_formats.iter().map(|f| f.as_ref().to_string()).collect(),
+            ),
+            _ => (Vec::new(), Vec::new()),
+        };
+        self
+    }
+
+    /// Sets how many preview images to save next to each model, and the most explicit rating
+    /// allowed among them.
+    pub fn with_previews(mut self, preview_images: usize, preview_max_nsfw: NSFW) -> Self {
+        self.preview_images = preview_images;
+        self.preview_max_nsfw = preview_max_nsfw;
+        self
+    }
+
+    /// Sets whether metadata sidecars are written next to downloaded files.
+    pub fn with_write_sidecars(mut self, write_sidecars: bool) -> Self {
+        self.write_sidecars = write_sidecars;
+        self
+    }
+
+    /// Sets where the manifest of downloaded files is kept.
+    pub fn with_manifest_path(mut self, manifest_path: Option<PathBuf>) -> Self {
+        self.manifest_path = manifest_path;
+        self
+    }
+
+    pub fn manifest_path(&self) -> PathBuf {
+        self.manifest_path
+            .clone()
+            .unwrap_or_else(manifest::get_default_manifest_path)
+    }
+
+    /// Points API requests somewhere other than Civitai, such as a local test server.
+    pub fn with_api_base_url(mut self, api_base_url: Option<String>) -> Self {
+        self.api_base_url = api_base_url;
+        self
+    }
+
+    pub fn api_base_url(&self) -> String {
+        self.api_base_url
+            .as_deref()
+            .unwrap_or(MAIN_API_URL)
+            .trim_end_matches('/')
+            .to_string()
+    }
+
+    /// Allows downloading files whose pickle or virus scans didn't succeed.
+    pub fn with_allow_failed_scans(mut self, allow_failed_scans: bool) -> Self {
+        self.allow_failed_scans = allow_failed_scans;
+        self
+    }
+
+    /// Sets the globals, as `module.name`, that pickles may import on top of
+    /// [`pickle::SAFE_GLOBALS`].
+    pub fn with_pickle_allowlist(mut self, pickle_allowlist: Vec<String>) -> Self {
+        self.pickle_allowlist = pickle_allowlist;
+        self
+    }
+
+    /// Sets where pickles that import anything unexpected are moved to.
+    pub fn with_quarantine_directory(mut self, quarantine_directory: Option<PathBuf>) -> Self {
+        self.quarantine_directory = quarantine_directory;
+        self
+    }
+
+    pub fn pickle_allowlist(&self) -> Vec<String> {
+        self.pickle_allowlist.clone()
+    }
+
+    pub fn quarantine_directory(&self) -> PathBuf {
+        self.quarantine_directory
+            .clone()
+            .unwrap_or_else(|| self.stable_diffusion_base_directory.join("quarantine"))
+    }
+
+    pub fn stable_diffusion_base_directory(&self) -> PathBuf {
+        self.stable_diffusion_base_directory.clone()
+    }
+
+    pub fn preview_images(&self) -> usize {
+        self.preview_images
+    }
+
+    pub fn preview_max_nsfw(&self) -> NSFW {
+        self.preview_max_nsfw.clone()
+    }
+
+    pub fn file_selection(&self) -> anyhow::Result<FileSelection> {
+        if self.all_files {
+            return Ok(FileSelection::All);
+        }
+        FileSelection::parse(&self.file_types, &self.file_formats)
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.retry_max_attempts.max(1),
+            base_delay: Duration::from_millis(self.retry_base_delay_ms),
+            max_delay: Duration::from_millis(self.retry_max_delay_ms),
+            max_retry_after: Duration::from_millis(self.retry_max_retry_after_ms),
+            jitter: self.retry_jitter,
         }
     }
 }
@@ -114,6 +551,29 @@ impl Default for Config {
             stable_diffusion_base_directory: default_stable_diffusion_fallback_directory(),
             model_format: ModelFormat::default(),
             resource_type: ResourceType::default(),
+            max_concurrent_downloads: default_max_concurrent_downloads(),
+            max_concurrent_requests: default_max_concurrent_requests(),
+            retry_max_attempts: default_retry_max_attempts(),
+            retry_base_delay_ms: default_retry_base_delay_ms(),
+            retry_max_delay_ms: default_retry_max_delay_ms(),
+            retry_max_retry_after_ms: default_retry_max_retry_after_ms(),
+            retry_jitter: default_retry_jitter(),
+            segmented_download_connections: default_segmented_download_connections(),
+            segmented_download_threshold_mb: default_segmented_download_threshold_mb(),
+            verify_segmented_downloads: default_verify_segmented_downloads(),
+            max_bytes_per_second: None,
+            max_bytes_per_second_per_download: None,
+            all_files: false,
+            file_types: Vec::new(),
+            file_formats: Vec::new(),
+            preview_images: 0,
+            preview_max_nsfw: NSFW::default(),
+            write_sidecars: default_write_sidecars(),
+            manifest_path: None,
+            api_base_url: None,
+            allow_failed_scans: false,
+            pickle_allowlist: Vec::new(),
+            quarantine_directory: None,
         }
     }
 }
@@ -139,22 +599,27 @@ pub struct Civit {
     pub client: reqwest::Client,
     pub config: Option<Config>,
     pub multi_progress: MultiProgress,
+    pub scheduler: Scheduler,
+    pub retry_policy: RetryPolicy,
+    pub rate_limiter: Option<RateLimiter>,
+    pub api_base_url: String,
 }
 
 impl Civit {
     #[tracing::instrument(level = "trace")]
     pub fn new(maybe_config: Option<Config>) -> Self {
+        let api_base_url = maybe_config
+            .as_ref()
+            .map(Config::api_base_url)
+            .unwrap_or(MAIN_API_URL.to_string());
         let jar = Jar::default();
-        if let Some(a) = maybe_config.clone() {
-            if let Some(t) = a.token {
-                let url = "https://civitai.com".parse::<Url>().unwrap();
-                let token = format!("__Secure-civitai-token={};", t);
-                let cookie = format!(
-                    "{} Domain=.civitai.com; Path=/; HttpOnly; Secure; SameSite=Lax",
-                    token
-                );
-                jar.add_cookie_str(cookie.as_str(), &url);
-                trace!("Added cookie {} to jar", cookie);
+        if let Some(t) = maybe_config.as_ref().and_then(|c| c.token.as_deref()) {
+            match get_token_cookie(&api_base_url, t) {
+                Ok((url, cookie)) => {
+                    jar.add_cookie_str(cookie.as_str(), &url);
+                    trace!("Added cookie {} to jar", cookie);
+                }
+                Err(e) => warn!(error =? e, "Failed to add the token cookie"),
             }
         }
 
@@ -168,10 +633,28 @@ impl Civit {
 
         let multi_progress = MultiProgress::new();
 
+        let scheduler = maybe_config
+            .as_ref()
+            .map(|c| Scheduler::new(c.max_concurrent_downloads, c.max_concurrent_requests))
+            .unwrap_or_default();
+        let retry_policy = maybe_config
+            .as_ref()
+            .map(Config::retry_policy)
+            .unwrap_or_default();
+        let rate_limiter = maybe_config
+            .as_ref()
+            .and_then(|c| c.max_bytes_per_second)
+            .filter(|rate| *rate > 0)
+            .map(RateLimiter::new);
+
         Civit {
             client,
             config: maybe_config.or(None),
             multi_progress,
+            scheduler,
+            retry_policy,
+            rate_limiter,
+            api_base_url,
         }
     }
 
@@ -260,7 +743,7 @@ impl Civit {
                   debug!(alt_type =? &alt);
                   return Ok(Some(alt.unwrap_or_default()))
                 } else {
-                    Ok(Some(vs.clone().first().clone().unwrap().clone()))
+                    Ok(Some(vs.first().unwrap().clone()))
                 }
             }
         } else {
@@ -336,20 +819,10 @@ impl Civit {
 
     #[tracing::instrument(level = "trace")]
     pub async fn get_model_details(self, model_id: String) -> Result<Model, anyhow::Error> {
-        let url = format!("{MAIN_API_URL}/models/{model_id}");
-        match self
-            .client
-            .get(&url)
-            .send()
-            .await
-            .inspect_err(|e| error!(error =? e, url =? url, model_id =? model_id, "Failed to fetch model details"))?
-            .json::<Model>()
+        let url = format!("{}/models/{model_id}", self.api_base_url);
+        self.get_json::<Model>(&url)
             .await
-            .inspect_err(|e| error!("Failed to parse JSON from URL: {url}. Error: {e}"))
-        {
-            Ok(o) => Ok(o),
-            Err(e) => Err(anyhow!(e)),
-        }
+            .inspect_err(|e| error!(error =? e, url =? url, model_id =? model_id, "Failed to fetch model details"))
     }
 
     #[tracing::instrument(level = "trace")]
@@ -357,64 +830,426 @@ impl Civit {
         self,
         model_version_id: i64,
     ) -> Result<ModelVersion, anyhow::Error> {
-        let url = format!("{MAIN_API_URL}/model-versions/{model_version_id}");
+        let url = format!("{}/model-versions/{model_version_id}", self.api_base_url);
         debug!("URL: {:#?}", url);
-        match self
-            .client
-            .get(&url)
-            .send()
+        self.get_json::<ModelVersion>(&url)
+            .await
+            .inspect_err(|e| debug!("Failed to fetch JSON from URL: {url}. Error: {e}"))
+    }
+
+    /// Looks up the model version a file with the given hash belongs to. Any hash Civitai
+    /// lists in [`model::model_version::Hashes`] works.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub async fn get_model_version_by_hash(
+        &self,
+        hash: &str,
+    ) -> anyhow::Result<Option<ModelVersion>> {
+        let url = format!("{}/model-versions/by-hash/{hash}", self.api_base_url);
+        self.find_json::<ModelVersion>(&url).await
+    }
+
+    /// Fetches and parses JSON from the API, waiting for a request slot and retrying transient
+    /// failures.
+    async fn get_json<T: DeserializeOwned>(&self, url: &str) -> anyhow::Result<T> {
+        self.find_json(url)
             .await?
-            .json::<ModelVersion>()
+            .ok_or(anyhow!("'{url}' returned {}", StatusCode::NOT_FOUND))
+    }
+
+    /// Like [`Civit::get_json`], but a 404 is `None` rather than an error.
+    async fn find_json<T: DeserializeOwned>(&self, url: &str) -> anyhow::Result<Option<T>> {
+        self.retry_policy
+            .run(url, || async move {
+                let _permit = self.scheduler.acquire_request().await?;
+                let response = self
+                    .client
+                    .get(url)
+                    .send()
+                    .await
+                    .with_context(|| format!("Failed to GET from '{url}'"))?;
+                if response.status() == StatusCode::NOT_FOUND {
+                    return Ok(None);
+                }
+                check_response(response)?
+                    .json::<T>()
+                    .await
+                    .map(Some)
+                    .with_context(|| format!("Failed to parse JSON from URL: {url}"))
+            })
             .await
-            .inspect_err(|e| debug!("Failed to parse JSON from URL: {url}. Error: {e}"))
-        {
-            Ok(o) => Ok(o),
-            Err(e) => Err(anyhow!(e)),
+    }
+
+    /// Identifies each of `paths` by hash, recording matches in the manifest and writing their
+    /// sidecars. Files already in the manifest are not looked up again, and quarantined files
+    /// are left alone.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub async fn scan_files(&self, paths: Vec<PathBuf>) -> Vec<ScanResult> {
+        let config = self.config.clone().unwrap_or_default();
+        let paths = scan::find_model_files(&paths, &[config.quarantine_directory()]);
+        let manifest = Manifest::load(&config.manifest_path()).unwrap_or_else(|e| {
+            warn!(error =? e, "Failed to load the manifest, identifying every file");
+            Manifest::default()
+        });
+        // Hashing is disk bound, so share the download limit rather than the request limit.
+        let mut results = stream::iter(paths)
+            .map(|path| {
+                let known = manifest.find_by_path(&path).is_some();
+                async move {
+                    if known {
+                        return ScanResult {
+                            path,
+                            sha256: None,
+                            outcome: ScanOutcome::Known,
+                        };
+                    }
+                    self.scan_file(path).await
+                }
+            })
+            .buffer_unordered(config.max_concurrent_downloads.max(1))
+            .collect::<Vec<_>>()
+            .await;
+        results.sort_by(|a, b| a.path.cmp(&b.path));
+        results
+    }
+
+    async fn scan_file(&self, path: PathBuf) -> ScanResult {
+        let hash_path = path.clone();
+        let sha256 = tokio::task::spawn_blocking(move || {
+            hashing::hash_file(&hash_path, HashKind::Sha256)
+        })
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|r| r);
+        let sha256 = match sha256 {
+            Ok(h) => h,
+            Err(e) => {
+                return ScanResult {
+                    path,
+                    sha256: None,
+                    outcome: ScanOutcome::failed(&e),
+                }
+            }
+        };
+        let outcome = match self.identify_file(&path, &sha256).await {
+            Ok(Some((model, model_version, file))) => {
+                let computed = ComputedHashes {
+                    sha256: Some(sha256.clone()),
+                    ..Default::default()
+                };
+                self.record_download(&model, &model_version, &file, &path, computed)
+                    .await;
+                let selected = SelectedFile {
+                    file: file.clone(),
+                    file_name: None,
+                    is_primary: true,
+                };
+                self.write_companion_files(&model, &model_version, &selected, &path)
+                    .await;
+                ScanOutcome::Identified {
+                    model: Box::new(model),
+                    model_version: Box::new(model_version),
+                    file: Box::new(file),
+                }
+            }
+            Ok(None) => ScanOutcome::Unmatched,
+            Err(e) => ScanOutcome::failed(&e),
+        };
+        ScanResult {
+            path,
+            sha256: Some(sha256),
+            outcome,
         }
     }
 
+    /// Finds the model, version and file that `sha256` belongs to.
+    async fn identify_file(
+        &self,
+        path: &Path,
+        sha256: &str,
+    ) -> anyhow::Result<Option<(Model, ModelVersion, ResourceFile)>> {
+        let model_version = match read_sidecar_version(path, sha256) {
+            Some(model_version) => model_version,
+            None => match self.get_model_version_by_hash(sha256).await? {
+                Some(model_version) => model_version,
+                None => {
+                    debug!("No match for {}", path.to_string_lossy());
+                    return Ok(None);
+                }
+            },
+        };
+        let files = model_version.files.clone().unwrap_or_default();
+        let file = files
+            .iter()
+            .find(|f| {
+                f.hashes
+                    .as_ref()
+                    .and_then(|h| h.sha256.as_deref())
+                    .is_some_and(|h| h.eq_ignore_ascii_case(sha256))
+            })
+            .or(files.first())
+            .cloned()
+            .ok_or(anyhow!("Model version {} has no files", model_version.id))?;
+        let model = match self
+            .clone()
+            .get_model_details(model_version.model_id.to_string())
+            .await
+        {
+            Ok(m) => m,
+            Err(e) => {
+                warn!(error =? e, "Failed to get model {}, using the version's summary", model_version.model_id);
+                let summary = model_version.model.clone().unwrap_or_default();
+                Model {
+                    id: model_version.model_id,
+                    name: summary.name,
+                    type_field: summary.type_field,
+                    nsfw: summary.nsfw,
+                    poi: summary.poi,
+                    ..Default::default()
+                }
+            }
+        };
+        Ok(Some((model, model_version, file)))
+    }
+
+    /// Looks up each installed model and compares its newest published version against the
+    /// installed version ids.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub async fn check_for_updates(
+        &self,
+        installed: &BTreeMap<i64, Vec<i64>>,
+    ) -> Vec<(i64, anyhow::Result<Option<AvailableUpdate>>)> {
+        join_all(installed.iter().map(|(model_id, version_ids)| async move {
+            let update = self
+                .clone()
+                .get_model_details(model_id.to_string())
+                .await
+                .with_context(|| format!("Failed to get model details for model {model_id}"))
+                .map(|model| update::find_update(&model, version_ids));
+            (*model_id, update)
+        }))
+        .await
+    }
+
+    /// Fetches a small resource, such as an image, into memory.
+    async fn get_bytes(&self, url: &str) -> anyhow::Result<Vec<u8>> {
+        self.retry_policy
+            .run(url, || async move {
+                let _permit = self.scheduler.acquire_request().await?;
+                let response = self
+                    .client
+                    .get(url)
+                    .send()
+                    .await
+                    .with_context(|| format!("Failed to GET from '{url}'"))?;
+                let bytes = check_response(response)?
+                    .bytes()
+                    .await
+                    .with_context(|| format!("Failed to read response from '{url}'"))?;
+                Ok(bytes.to_vec())
+            })
+            .await
+    }
+
     #[tracing::instrument(level = "debug", skip(self))]
     pub async fn download_latest_resource_for_model(
         self,
         model: Model,
         all: bool,
-    ) -> anyhow::Result<()> {
+    ) -> Vec<DownloadReport> {
         let versions = model.clone().model_versions;
         match all {
-            false => {
-                let first = versions.first().unwrap().to_owned().clone();
-                self.clone().download_file(&first, model.clone()).await
-            }
+            false => match versions.first() {
+                Some(first) => self.clone().download_version(first, model.clone()).await,
+                None => vec![DownloadReport::new(
+                    &model,
+                    None,
+                    None,
+                    DownloadOutcome::Failed {
+                        reason: format!("Model {} has no versions", model.id),
+                    },
+                )],
+            },
             true => {
                 join_all(
                     versions
                         .iter()
-                        .map(|v| async { self.clone().download_file(v, model.clone()).await })
+                        .map(|v| async { self.clone().download_version(v, model.clone()).await })
                         .collect::<Vec<_>>(),
                 )
-                .await;
-                Ok(())
+                .await
+                .into_iter()
+                .flatten()
+                .collect()
             }
         }
     }
 
+    /// Resolves the files each of `versions` would download and the directory they would be saved
+    /// to, so the space a batch needs can be checked before anything is fetched.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub async fn plan_downloads(
+        &self,
+        model: &Model,
+        versions: &[ModelVersion],
+    ) -> anyhow::Result<Vec<PlannedDownload>> {
+        let base_directory = self
+            .config
+            .clone()
+            .unwrap_or_default()
+            .stable_diffusion_base_directory;
+        let model_type = ModelType::from_str(&model.type_field).unwrap_or(ModelType::Unknown);
+        let directory = self.get_download_folder_from_model_type(base_directory.clone(), model_type);
+        let vae_directory = self.get_download_folder_from_model_type(base_directory, ModelType::VAE);
+        let mut planned = Vec::new();
+        for version in versions {
+            for selected in self.select_files(version).await? {
+                if self.check_scans(&selected.file).is_some() {
+                    continue;
+                }
+                let is_vae = matches!(
+                    ResourceType::from_str(&selected.file.type_field),
+                    Ok(ResourceType::VAE)
+                );
+                // Size partial downloads under the name they are actually saved as.
+                let file_name = selected.file_name.unwrap_or(selected.file.name.clone());
+                planned.push(PlannedDownload {
+                    directory: if is_vae { &vae_directory } else { &directory }.clone(),
+                    file_name: content_disposition::sanitize_filename(&file_name).unwrap_or(file_name),
+                    size_bytes: selected.file.size_bytes().unwrap_or_default(),
+                });
+            }
+        }
+        Ok(planned)
+    }
+
+    /// Runs the same resolution as [`Civit::download_file`] without downloading anything,
+    /// describing what the download would do.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub async fn plan_download(
+        &self,
+        model: &Model,
+        model_version: &ModelVersion,
+    ) -> anyhow::Result<Vec<PlannedItem>> {
+        let manifest_path = self.config.clone().unwrap_or_default().manifest_path();
+        let manifest = Manifest::load(&manifest_path).unwrap_or_else(|e| {
+            warn!(error =? e, "Failed to load the manifest, planning without it");
+            Manifest::default()
+        });
+        let mut items = Vec::new();
+        for selected in self.select_files(model_version).await? {
+            items.push(self.plan_file(model, model_version, selected, &manifest).await?);
+        }
+        Ok(items)
+    }
+
+    async fn plan_file(
+        &self,
+        model: &Model,
+        model_version: &ModelVersion,
+        selected: SelectedFile,
+        manifest: &Manifest,
+    ) -> anyhow::Result<PlannedItem> {
+        let target_file = selected.file;
+        let base_directory = self
+            .config
+            .clone()
+            .unwrap_or_default()
+            .stable_diffusion_base_directory;
+        let model_directory = self
+            .get_download_folder_for_file(base_directory, model_version, &target_file)
+            .await?;
+        // Unless the name is forced, the server usually names the file the same way, but that
+        // is only known once the download starts.
+        let name = selected.file_name.as_deref().unwrap_or(&target_file.name);
+        let file_name = content_disposition::sanitize_filename(name)
+            .ok_or(anyhow!("'{}' is not a usable filename", name))?;
+        let target_path = content_disposition::join_within(&model_directory, &file_name)?;
+
+        let action = if let Some(reason) = self.check_scans(&target_file) {
+            PlannedAction::Blocked { reason }
+        } else if target_path.exists() {
+            // Hashing every existing file would make planning as slow as downloading, so trust
+            // the manifest and otherwise only compare sizes.
+            match target_path.metadata().map(|m| m.len()) {
+                Ok(size) if manifest
+                    .find_by_path(&target_path)
+                    .is_some_and(|e| e.file_id == target_file.id && e.size_bytes == size) =>
+                {
+                    PlannedAction::SkipAlreadyPresent
+                }
+                Ok(size) if target_file.size_bytes() == Some(size) => PlannedAction::Verify,
+                Ok(_) => PlannedAction::Overwrite,
+                Err(e) => PlannedAction::Conflict {
+                    reason: format!("Failed to read metadata: {e}"),
+                },
+            }
+        } else {
+            match get_part_path(&target_path).metadata().map(|m| m.len()) {
+                Ok(bytes_present) if bytes_present > 0 => PlannedAction::Resume { bytes_present },
+                _ => PlannedAction::Download,
+            }
+        };
+
+        Ok(PlannedItem {
+            model_id: model.id,
+            model_name: model.name.clone(),
+            model_version_id: model_version.id,
+            model_version_name: model_version.name.clone(),
+            file_id: target_file.id,
+            file_name,
+            file_type: target_file.type_field.clone(),
+            format: target_file.format.clone(),
+            size_bytes: target_file.size_bytes(),
+            url: target_file.download_url.clone(),
+            target_path,
+            action,
+        })
+    }
+
     pub async fn check_if_file_exists_and_matches_hash(
         self,
         path: PathBuf,
         file: ResourceFile,
     ) -> anyhow::Result<bool> {
-        let file_exists = path.exists();
-        if !file_exists {
-            return Ok(false);
+        Ok(self.find_existing_file(&path, &file).await?.is_some())
+    }
+
+    /// Checks whether `path` already holds `file`, returning the hashes it was verified with.
+    /// These are empty when Civitai reported no hash we can check and only the sizes matched.
+    async fn find_existing_file(
+        &self,
+        path: &Path,
+        file: &ResourceFile,
+    ) -> anyhow::Result<Option<ComputedHashes>> {
+        if !path.exists() {
+            return Ok(None);
         }
 
-        let size1 = file.size_kb.unwrap();
-        let size2 = path.metadata().unwrap().len() as f64 / 1024.0;
-        debug!("Checking sizes {} and {}...", &size1, &size2);
+        if let Some(hashes) = file.hashes.clone() {
+            let verify_path = path.to_path_buf();
+            let verified = tokio::task::spawn_blocking(move || {
+                hashing::hash_and_verify_file(&verify_path, &hashes)
+            })
+            .await??;
+            if let Some(computed) = verified {
+                debug!("{} matches the expected hash", path.to_string_lossy());
+                return Ok(Some(computed));
+            }
+        }
+
+        let Some(expected_size) = file.size_bytes() else {
+            debug!("No size reported for {}, can't tell if it is already there", file.name);
+            return Ok(None);
+        };
+        let size = path
+            .metadata()
+            .with_context(|| format!("Failed to read metadata of '{}'", path.to_string_lossy()))?
+            .len();
+        debug!("Checking sizes {} and {}...", expected_size, size);
 
-        let same = file_exists && size1.eq(&size2);
+        let same = size == expected_size;
         debug!("Same: {}", &same);
-        Ok(same)
+        Ok(same.then(ComputedHashes::default))
     }
 
     #[tracing::instrument(level = "debug", skip(self))]
@@ -422,108 +1257,570 @@ impl Civit {
         self,
         model: Model,
         oid: String,
-    ) -> anyhow::Result<()> {
+    ) -> Vec<DownloadReport> {
         let versions = model.clone().model_versions;
         let target = versions
             .iter()
-            .find(|version| version.id.to_string().eq(&oid))
-            .ok_or(Err(anyhow!(
-                "Failed to find model version {} for model {}",
-                oid,
-                model.id.to_string()
-            )));
+            .find(|version| version.id.to_string().eq(&oid));
         match target {
-            Ok(t) => self.clone().download_file(t, model.clone()).await,
-            Err(e) => e,
+            Some(t) => self.clone().download_version(t, model.clone()).await,
+            None => vec![DownloadReport::new(
+                &model,
+                None,
+                None,
+                DownloadOutcome::Failed {
+                    reason: format!(
+                        "Failed to find model version {} for model {}",
+                        oid, model.id
+                    ),
+                },
+            )],
         }
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
-    pub async fn download_file(
+    pub async fn download_file(self, model_version: &ModelVersion, model: Model) -> DownloadReport {
+        let Some(alt) = model_version.files.as_ref().and_then(|f| f.first()).cloned() else {
+            return DownloadReport::new(
+                &model,
+                Some(model_version),
+                None,
+                DownloadOutcome::Failed {
+                    reason: format!("Model version {} has no files", model_version.id),
+                },
+            );
+        };
+        let target_file = match self
+            .clone()
+            .get_optimal_file_from_preferred_model_format(model_version.clone())
+            .await
+        {
+            Ok(f) => f.unwrap_or(alt),
+            Err(e) => {
+                return DownloadReport::new(
+                    &model,
+                    Some(model_version),
+                    None,
+                    DownloadOutcome::failed(&e),
+                )
+            }
+        };
+        trace!("Target file: {:?}", &target_file);
+
+        let selected = SelectedFile {
+            file: target_file,
+            file_name: None,
+            is_primary: true,
+        };
+        self.download_selected_file(model_version, &model, &selected)
+            .await
+    }
+
+    /// Downloads the files of `model_version` picked by the configured [`FileSelection`].
+    #[tracing::instrument(level = "trace", skip(self))]
+    pub async fn download_version(
         self,
         model_version: &ModelVersion,
         model: Model,
-    ) -> anyhow::Result<()> {
-        let path = &self
-            .config
-            .clone()
-            .unwrap()
-            .stable_diffusion_base_directory
-            .clone();
+    ) -> Vec<DownloadReport> {
+        let selected = match self.select_files(model_version).await {
+            Ok(s) => s,
+            Err(e) => {
+                return vec![DownloadReport::new(
+                    &model,
+                    Some(model_version),
+                    None,
+                    DownloadOutcome::failed(&e),
+                )]
+            }
+        };
+        if selected.is_empty() {
+            return vec![DownloadReport::new(
+                &model,
+                Some(model_version),
+                None,
+                DownloadOutcome::SkippedByPolicy {
+                    reason: format!(
+                        "No files of model version {} match the file selection",
+                        model_version.id
+                    ),
+                },
+            )];
+        }
+        // A renamed config has to share the stem the checkpoint is actually saved under, which
+        // is only known once the server has named it, so it is downloaded last.
+        let (renamed, others): (Vec<_>, Vec<_>) = selected
+            .into_iter()
+            .partition(|s| s.file_name.is_some() && !s.is_primary);
+        let mut reports = join_all(
+            others
+                .iter()
+                .map(|s| self.download_selected_file(model_version, &model, s)),
+        )
+        .await;
+        let primary_stem = others
+            .iter()
+            .zip(&reports)
+            .find(|(s, _)| s.is_primary)
+            .and_then(|(_, r)| r.outcome.path()?.file_stem())
+            .map(|s| s.to_string_lossy().to_string());
+        for mut selected in renamed {
+            if let Some(stem) = &primary_stem {
+                selected.file_name = Some(get_config_file_name(stem, &selected.file));
+            }
+            reports.push(
+                self.download_selected_file(model_version, &model, &selected)
+                    .await,
+            );
+        }
+        reports
+    }
 
-        let alt = model_version
-            .clone()
-            .files
-            .unwrap()
+    /// Picks the files of `model_version` to download according to the configured
+    /// [`FileSelection`].
+    pub async fn select_files(
+        &self,
+        model_version: &ModelVersion,
+    ) -> anyhow::Result<Vec<SelectedFile>> {
+        let files = model_version.files.clone().unwrap_or_default();
+        let alt = files
             .first()
-            .unwrap()
-            .clone();
-        let target_file = self
+            .cloned()
+            .ok_or(anyhow!("Model version {} has no files", model_version.id))?;
+        let primary = self
             .clone()
             .get_optimal_file_from_preferred_model_format(model_version.clone())
             .await?
             .unwrap_or(alt);
-        trace!("Target file: {:?}", &target_file);
+        let selection = self
+            .config
+            .as_ref()
+            .map(Config::file_selection)
+            .transpose()?
+            .unwrap_or_default();
+        debug!(selection =? &selection, primary =? &primary.name, "Selecting files");
+        if selection == FileSelection::Optimal {
+            return Ok(vec![SelectedFile {
+                file: primary,
+                file_name: None,
+                is_primary: true,
+            }]);
+        }
+
+        // The checkpoint is usually saved under its sanitized name, but the server has the final
+        // say; see `download_version`.
+        let primary_stem = content_disposition::sanitize_filename(&primary.name).and_then(|n| {
+            Path::new(&n)
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+        });
+        let mut config_renamed = false;
+        Ok(files
+            .into_iter()
+            .filter(|f| selection.matches(f))
+            .map(|file| {
+                let is_config = matches!(
+                    ResourceType::from_str(&file.type_field),
+                    Ok(ResourceType::Config)
+                );
+                // UIs only pick up a config when it shares the checkpoint's stem. Only one config
+                // can, so any others keep their own names.
+                let file_name = match (is_config && !config_renamed, &primary_stem) {
+                    (true, Some(stem)) => {
+                        config_renamed = true;
+                        Some(get_config_file_name(stem, &file))
+                    }
+                    _ => None,
+                };
+                SelectedFile {
+                    is_primary: file.id == primary.id,
+                    file,
+                    file_name,
+                }
+            })
+            .collect())
+    }
+
+    /// Resolves the directory a file is saved to. VAEs go to the VAE folder, everything else
+    /// goes next to the model.
+    async fn get_download_folder_for_file(
+        &self,
+        path: PathBuf,
+        model_version: &ModelVersion,
+        file: &ResourceFile,
+    ) -> anyhow::Result<PathBuf> {
+        match ResourceType::from_str(&file.type_field) {
+            Ok(ResourceType::VAE) => Ok(self.get_download_folder_from_model_type(path, ModelType::VAE)),
+            _ => {
+                self.clone()
+                    .get_download_folder_from_model_version(path, model_version.clone())
+                    .await
+            }
+        }
+    }
+
+    async fn download_selected_file(
+        &self,
+        model_version: &ModelVersion,
+        model: &Model,
+        selected: &SelectedFile,
+    ) -> DownloadReport {
+        let (outcome, computed) = match self.check_scans(&selected.file) {
+            Some(reason) => (DownloadOutcome::SkippedByPolicy { reason }, ComputedHashes::default()),
+            None => self
+                .download_resource_file(model_version, model, selected)
+                .await
+                .unwrap_or_else(|e| {
+                    error!(error =? e, "Failed to download {}", &selected.file.name);
+                    (DownloadOutcome::failed(&e), ComputedHashes::default())
+                }),
+        };
+        if let DownloadOutcome::Downloaded { path, .. }
+        | DownloadOutcome::SkippedAlreadyPresent { path } = &outcome
+        {
+            self.record_download(model, model_version, &selected.file, path, computed)
+                .await;
+            self.write_companion_files(model, model_version, selected, path)
+                .await;
+        }
+        DownloadReport::new(
+            model,
+            Some(model_version),
+            Some(selected.file_name.clone().unwrap_or(selected.file.name.clone())),
+            outcome,
+        )
+    }
+
+    /// Returns why `file` must not be downloaded because of its scan results, or `None` if it
+    /// may be.
+    fn check_scans(&self, file: &ResourceFile) -> Option<String> {
+        let failed_scans = file.get_failed_scans()?;
+        if self.config.as_ref().is_some_and(|c| c.allow_failed_scans) {
+            warn!(file = file.name, failed_scans, "Downloading despite failed scans as requested");
+            return None;
+        }
+        Some(format!(
+            "Refusing to download {} as its scans didn't succeed: {}. Pass --allow-failed-scans to download it anyway",
+            file.name, failed_scans
+        ))
+    }
+
+    /// Adds a downloaded file to the manifest along with the hashes it was verified with.
+    /// Failures are logged rather than failing the download.
+    async fn record_download(
+        &self,
+        model: &Model,
+        model_version: &ModelVersion,
+        file: &ResourceFile,
+        path: &Path,
+        computed: ComputedHashes,
+    ) {
+        let manifest_path = self.config.clone().unwrap_or_default().manifest_path();
+        let entry = match ManifestEntry::new(model, model_version, file, path) {
+            Ok(e) => e.with_computed_hashes(computed),
+            Err(e) => {
+                warn!(error =? e, "Failed to describe {} for the manifest", path.to_string_lossy());
+                return;
+            }
+        };
+        let recorded = tokio::task::spawn_blocking(move || manifest::record(&manifest_path, entry))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|r| r);
+        if let Err(e) = recorded {
+            warn!(error =? e, "Failed to record {} in the manifest", path.to_string_lossy());
+        }
+    }
+
+    /// Writes the files that accompany a downloaded model. Failures are logged rather than
+    /// failing the download.
+    async fn write_companion_files(
+        &self,
+        model: &Model,
+        model_version: &ModelVersion,
+        selected: &SelectedFile,
+        model_path: &Path,
+    ) {
+        // Sidecars and previews describe the model itself, not its configs or VAEs.
+        if !selected.is_primary {
+            return;
+        }
+        if self.config.as_ref().is_none_or(|c| c.write_sidecars) {
+            if let Err(e) = sidecar::write_civitai_info(model_path, model, model_version) {
+                warn!(error =? e, "Failed to write .civitai.info for {}", model_path.to_string_lossy());
+            }
+            if let Err(e) = sidecar::write_extra_network_info(model_path, model, model_version) {
+                warn!(error =? e, "Failed to write extra network .json for {}", model_path.to_string_lossy());
+            }
+        }
+        if let Err(e) = self.save_previews(model_version, model_path).await {
+            warn!(error =? e, "Failed to save previews for {}", model_path.to_string_lossy());
+        }
+    }
+
+    /// Saves the configured number of preview images of `model_version` as PNGs next to
+    /// `model_path`, leaving existing previews alone.
+    pub async fn save_previews(
+        &self,
+        model_version: &ModelVersion,
+        model_path: &Path,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let Some(config) = self.config.as_ref().filter(|c| c.preview_images > 0) else {
+            return Ok(Vec::new());
+        };
+        let images = model_version.images.clone().unwrap_or_default();
+        let selected =
+            preview::select_images(&images, config.preview_images, &config.preview_max_nsfw);
+        debug!(
+            available = images.len(),
+            selected = selected.len(),
+            "Saving previews for model version {}",
+            model_version.id
+        );
+
+        let mut saved = Vec::new();
+        for (index, image) in selected.into_iter().enumerate() {
+            let preview_path = preview::get_preview_path(model_path, index);
+            if preview_path.exists() {
+                trace!("{} already exists", preview_path.to_string_lossy());
+                continue;
+            }
+            // One missing or broken image shouldn't cost the rest.
+            match self.save_preview(&image.url, &preview_path).await {
+                Ok(()) => saved.push(preview_path),
+                Err(e) => warn!(error =? e, "Failed to save preview '{}'", &image.url),
+            }
+        }
+        Ok(saved)
+    }
+
+    /// Downloads the image at `url` and writes it to `preview_path` as a PNG.
+    async fn save_preview(&self, url: &str, preview_path: &Path) -> anyhow::Result<()> {
+        let bytes = self.get_bytes(url).await?;
+        let png = tokio::task::spawn_blocking(move || preview::convert_to_png(&bytes))
+            .await?
+            .with_context(|| format!("Failed to convert preview '{}'", url))?;
+        let part_path = get_part_path(preview_path);
+        std::fs::write(&part_path, png)
+            .with_context(|| format!("Failed to write '{}'", part_path.to_string_lossy()))?;
+        persist_download(&part_path, preview_path)
+    }
+
+    /// Downloads a file of `model_version` into the directory for its type.
+    async fn download_resource_file(
+        &self,
+        model_version: &ModelVersion,
+        model: &Model,
+        selected: &SelectedFile,
+    ) -> anyhow::Result<(DownloadOutcome, ComputedHashes)> {
+        let target_file = &selected.file;
+        let path = &self
+            .config
+            .clone()
+            .unwrap()
+            .stable_diffusion_base_directory
+            .clone();
 
         let url = &target_file.download_url.clone();
         trace!("URL: {}", &url);
 
+        // A file that was quarantined before would only be quarantined again.
+        let quarantine_directory =
+            get_quarantine_directory(&self.config.clone().unwrap_or_default(), model_version);
+        if let Some(quarantined) = QuarantineRecord::find(&quarantine_directory, target_file.id) {
+            warn!(path =? &quarantined, "{} was quarantined before. Not downloading...", target_file.name);
+            let outcome = DownloadOutcome::SkippedByPolicy {
+                reason: format!("Already quarantined to '{}'", quarantined.to_string_lossy()),
+            };
+            return Ok((outcome, ComputedHashes::default()));
+        }
+
+        let _permit = self
+            .scheduler
+            .acquire_download(
+                &self.multi_progress,
+                &format!("{} (version {} of {model:?})", target_file.name, model_version.id),
+            )
+            .await?;
+
         let model_directory = self
-            .clone()
-            .get_download_folder_from_model_version(path.clone(), model_version.clone())
+            .get_download_folder_for_file(path.clone(), model_version, target_file)
             .await?;
-        let result = self
-            .client
-            .get(url.clone())
-            .send()
+
+        let throttle = Throttle::new(
+            self.rate_limiter.clone(),
+            self.config
+                .as_ref()
+                .and_then(|c| c.max_bytes_per_second_per_download)
+                .filter(|rate| *rate > 0),
+        );
+        self.retry_policy
+            .run(url, || {
+                self.fetch_file(
+                    url,
+                    &model_directory,
+                    model_version,
+                    model,
+                    selected,
+                    &throttle,
+                )
+            })
             .await
-            .or(Err(anyhow!("Failed to GET from '{}'", &url)))?;
+    }
+
+    /// Makes a single attempt at downloading `selected` into `model_directory`, resuming from
+    /// any partial download left by an earlier attempt. Returns the hashes the file was verified
+    /// with along with the outcome.
+    async fn fetch_file(
+        &self,
+        url: &str,
+        model_directory: &Path,
+        model_version: &ModelVersion,
+        model: &Model,
+        selected: &SelectedFile,
+        throttle: &Throttle,
+    ) -> anyhow::Result<(DownloadOutcome, ComputedHashes)> {
+        let target_file = &selected.file;
+        // Ask for the rest of a partial download straight away when there is one under the name
+        // the file is expected to be saved as, so resuming doesn't take a second request.
+        let requested_from = content_disposition::sanitize_filename(
+            selected.file_name.as_deref().unwrap_or(&target_file.name),
+        )
+        .and_then(|f| content_disposition::join_within(model_directory, &f).ok())
+        .and_then(|p| get_part_path(&p).metadata().ok())
+        .map(|m| m.len())
+        .unwrap_or(0);
+        let result = self.request_from(url, requested_from).await?;
 
         let headers = result.headers();
         trace!("Headers: {:#?}", &headers);
+        // Login walls and error pages come back as 200s, so don't save them as models.
+        let is_html = headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.trim_start().starts_with("text/html"));
+        if is_html && result.status().is_success() && is_safetensors(target_file) {
+            return Err(anyhow!(
+                "'{}' returned an HTML page instead of {}",
+                &url,
+                &target_file.name
+            ));
+        }
 
-        let content_disposition_raw = result
+        let content_disposition = result
             .headers()
-            .iter()
-            .find(|(x, _)| x.as_str().eq("content-disposition"))
-            .ok_or(anyhow!("Failed to get content disposition from '{}'", &url))?;
-        let content_disposition = String::from_utf8_lossy(content_disposition_raw.1.as_bytes()).to_string();
-        let filename = content_disposition
-            .split("filename=")
-            .last()
-            .unwrap()
-            .replace('"', "");
-        trace!("Content Disposition for {:?}: {:?}", &url, &filename);
-
+            .get(CONTENT_DISPOSITION)
+            .map(|v| String::from_utf8_lossy(v.as_bytes()).to_string());
+        trace!("Content Disposition for {:?}: {:?}", &url, &content_disposition);
+        let filename = selected
+            .file_name
+            .as_deref()
+            .and_then(content_disposition::sanitize_filename)
+            .or_else(|| content_disposition.as_deref().and_then(content_disposition::parse_filename))
+            .and_then(|f| content_disposition::sanitize_filename(&f))
+            .or_else(|| {
+                debug!(
+                    "No usable filename in content disposition from '{}'. Falling back to '{}'",
+                    &url, &target_file.name
+                );
+                content_disposition::sanitize_filename(&target_file.name)
+            })
+            .ok_or(anyhow!("Failed to get a usable filename for '{}'", &url))?;
 
-        let final_path = model_directory.join(&filename);
+        let final_path = content_disposition::join_within(model_directory, &filename)?;
         debug!("Final path: {}", final_path.to_string_lossy());
-        
 
-        let same = self
-            .clone()
-            .check_if_file_exists_and_matches_hash(final_path.clone(), target_file.clone())
-            .await?;
-        if same {
-            let message = format!(
+        if let Some(computed) = self.find_existing_file(&final_path, target_file).await? {
+            // Files put there by hand or by an older version never went through the analysis.
+            if let Some(outcome) =
+                self.quarantine_if_flagged(&final_path, &final_path, model_version, target_file)?
+            {
+                return Ok((outcome, computed));
+            }
+            warn!(
                 "{:?} already exists! Not downloading...",
                 final_path.to_string_lossy()
             );
-            warn!("{}", message);
-            return Err(anyhow!(message));
+            return Ok((DownloadOutcome::SkippedAlreadyPresent { path: final_path }, computed));
         }
 
-        let total_size = result
-            .content_length()
-            .ok_or(anyhow!("Failed to get content length from '{}'", &url))?;
+        let part_path = get_part_path(&final_path);
+        let mut resume_from = part_path.metadata().map(|m| m.len()).unwrap_or(0);
+        let mut response = result;
+        if resume_from != requested_from {
+            // The server named the file differently than expected, so the first request didn't
+            // fit the partial download that is actually there.
+            debug!(part_path =? &part_path, resume_from, requested_from, "Requesting again to match partial download");
+            response = self.request_from(url, resume_from).await?;
+        }
+        if resume_from > 0 {
+            debug!(part_path =? &part_path, resume_from, "Found partial download");
+            match response.status() {
+                StatusCode::PARTIAL_CONTENT
+                    if get_content_range_start(response.headers()) == Some(resume_from) =>
+                {
+                    debug!("Resuming download of {} at byte {}", &filename, resume_from);
+                }
+                StatusCode::RANGE_NOT_SATISFIABLE
+                    if Some(resume_from)
+                        == get_content_range_total(response.headers())
+                            .or_else(|| target_file.size_bytes()) =>
+                {
+                    debug!("Partial download of {} is already complete", &filename);
+                    let hasher = prime_hasher(
+                        StreamingHasher::for_expected(target_file.hashes.as_ref()),
+                        &part_path,
+                    )
+                    .await?;
+                    return self.finish_download(
+                        &part_path,
+                        final_path,
+                        model_version,
+                        target_file,
+                        hasher.finalize(),
+                        resume_from,
+                    );
+                }
+                StatusCode::OK => {
+                    warn!("Server ignored range request for {}. Restarting download ...", &filename);
+                    resume_from = 0;
+                }
+                status => {
+                    warn!(status =? status, "Unable to resume download of {}. Restarting download ...", &filename);
+                    resume_from = 0;
+                    response = self.request_from(url, 0).await?;
+                }
+            }
+        }
+
+        // Ranged responses only carry the length of the range, so take the total from
+        // Content-Range. Mirrors using chunked transfer encoding don't send a length at all, so
+        // fall back to the size Civitai reported, and failing that, to an indeterminate spinner.
+        let content_length = match response.status() {
+            StatusCode::PARTIAL_CONTENT => get_content_range_total(response.headers()),
+            _ => response.content_length(),
+        };
+        let total_size = content_length.or_else(|| target_file.size_bytes());
+        if content_length.is_none() {
+            debug!(url, total_size, "No content length in response");
+        }
+
+        let mut hasher = StreamingHasher::for_expected(target_file.hashes.as_ref());
+        if resume_from > 0 {
+            hasher = prime_hasher(hasher, &part_path).await?;
+        }
 
         let check_format = ModelFormat::from_str(&target_file.clone().format.unwrap_or_default()).unwrap_or(ModelFormat::Other);
         let check_type = ResourceType::from_str(&target_file.clone().type_field).unwrap_or(ResourceType::Unknown);
-        let pb = self.multi_progress.add(ProgressBar::new(total_size)
-            .with_prefix(filename)
+        let (pb, template) = match total_size {
+            Some(size) => (ProgressBar::new(size), "{msg}\n{spinner:.green} [{prefix}] [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})"),
+            None => (ProgressBar::new_spinner(), "{msg}\n{spinner:.green} [{prefix}] [{elapsed_precise}] {bytes} ({bytes_per_sec})"),
+        };
+        let pb = self.multi_progress.add(pb
+            .with_prefix(filename.clone())
             .with_message(format!("Attempting to download version {} for {model:?} (format: {:?}/{:?}) ...", model_version.id, check_type, check_format))
             .with_style(ProgressStyle::default_bar()
-                .template("{msg}\n{spinner:.green} [{prefix}] [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")?
+                .template(template)?
                 .progress_chars("#>-")))
             .with_finish(indicatif::ProgressFinish::WithMessage(format!(
                         "Downloaded {} ({:?}/{:?}) to {}",
@@ -533,25 +1830,679 @@ impl Civit {
                         final_path.to_string_lossy()
             ).into()));
 
+        if let (0, Some(total_size)) = (resume_from, content_length) {
+            let segmented_path = get_segmented_path(&final_path);
+            let segment_count = self
+                .get_segment_count(&response, total_size)
+                .map(|(connections, permit)| (connections, Some(permit)))
+                .or_else(|| {
+                    // Finish an interrupted segmented download even without spare slots.
+                    get_hasher_state_path(&segmented_path).exists().then_some((1, None))
+                });
+            if let Some((connections, _extra_downloads)) = segment_count {
+                drop(response);
+                let result = async {
+                    let segments = self
+                        .fetch_segments(
+                            url,
+                            &segmented_path,
+                            total_size,
+                            connections,
+                            &pb,
+                            throttle,
+                        )
+                        .await?;
+                    let mut computed = ComputedHashes {
+                        blake3: hashing::combine_blake3_segments(segments),
+                        ..Default::default()
+                    };
+                    // Segments arrive out of order, so anything but BLAKE3 can only be computed
+                    // by reading the file back once it is complete.
+                    let verify = self.config.as_ref().is_some_and(|c| c.verify_segmented_downloads);
+                    let needs_read_back = target_file.hashes.as_ref().is_some_and(|h| {
+                        HashKind::Blake3.expected_from(h).is_none()
+                            && hashing::get_strongest_expected_hash(h).is_some()
+                    });
+                    if verify && needs_read_back {
+                        debug!(path =? &segmented_path, "Reading back segmented download to verify it");
+                        let hasher = prime_hasher(
+                            StreamingHasher::for_expected(target_file.hashes.as_ref()),
+                            &segmented_path,
+                        )
+                        .await?;
+                        computed = ComputedHashes {
+                            blake3: computed.blake3,
+                            ..hasher.finalize()
+                        };
+                    }
+                    self.finish_download(
+                        &segmented_path,
+                        final_path,
+                        model_version,
+                        target_file,
+                        computed,
+                        total_size,
+                    )
+                }
+                .await;
+                if let Err(e) = &result {
+                    pb.abandon_with_message(format!("Failed to download {}: {}", &filename, e));
+                    if !is_transient(e) {
+                        debug!(path =? &segmented_path, "Removing segmented download after permanent failure");
+                        std::fs::remove_file(&segmented_path).ok();
+                        std::fs::remove_file(get_hasher_state_path(&segmented_path)).ok();
+                    }
+                }
+                return result;
+            }
+        }
+
         // download chunks
-        let mut file = File::create(&final_path).or(Err(anyhow!(
+        let mut file = if resume_from > 0 {
+            OpenOptions::new().append(true).open(&part_path)
+        } else {
+            std::fs::remove_file(get_hasher_state_path(&part_path)).ok();
+            File::create(&part_path)
+        }
+        .or(Err(anyhow!(
             "Failed to create file '{}'",
-            final_path.to_string_lossy()
+            part_path.to_string_lossy()
         )))?;
-        let mut downloaded: u64 = 0;
-        let mut stream = result.bytes_stream();
+        let mut downloaded: u64 = resume_from;
+        pb.set_position(downloaded);
+        let mut stream = response.bytes_stream();
+
+        let streamed: anyhow::Result<()> = async {
+            while let Some(item) = stream.next().await {
+                let chunk = item.context("Failed to read chunk from stream")?;
+                write_part(&mut file, &part_path, &mut hasher, &chunk)?;
+                downloaded += chunk.len() as u64;
+                pb.set_position(downloaded);
+                throttle.consume(chunk.len() as u64).await;
+            }
+
+            if let Some(length) = content_length.filter(|l| downloaded < *l) {
+                return Err(TransientError {
+                    message: format!(
+                        "Download of '{}' ended early ({}/{} bytes)",
+                        &url, downloaded, length
+                    ),
+                    retry_after: None,
+                }
+                .into());
+            }
+            Ok(())
+        }
+        .await;
+        if let Err(e) = streamed {
+            pb.abandon_with_message(format!("Failed to download {}: {}", &filename, e));
+            drop(file);
+            if !is_transient(&e) {
+                // Only keep partial downloads that a retry or the next run can resume.
+                debug!(part_path =? &part_path, "Removing partial download after permanent failure");
+                std::fs::remove_file(&part_path).ok();
+                std::fs::remove_file(get_hasher_state_path(&part_path)).ok();
+            }
+            return Err(e);
+        }
+
+        drop(file);
+        let result = self.finish_download(
+            &part_path,
+            final_path,
+            model_version,
+            target_file,
+            hasher.finalize(),
+            downloaded,
+        );
+        if let Err(e) = &result {
+            pb.abandon_with_message(format!("Failed to download {}: {}", &filename, e));
+        }
+        result
+    }
+
+    /// Sends a GET for `url`, asking for everything from byte `from` on when it isn't 0.
+    ///
+    /// A range that can't be satisfied is returned as is rather than as an error, since that is
+    /// how servers report a partial download that is already complete.
+    async fn request_from(&self, url: &str, from: u64) -> anyhow::Result<Response> {
+        let mut request = self.client.get(url);
+        if from > 0 {
+            request = request.header(RANGE, format!("bytes={from}-"));
+        }
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to GET from '{}'", &url))?;
+        if from > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            return Ok(response);
+        }
+        check_response(response)
+    }
+
+    /// Verifies a completed download and moves it to `final_path`, unless it is a pickle that
+    /// imports anything outside the allowlist, in which case it is quarantined instead.
+    fn finish_download(
+        &self,
+        part_path: &Path,
+        final_path: PathBuf,
+        model_version: &ModelVersion,
+        target_file: &ResourceFile,
+        computed: ComputedHashes,
+        bytes: u64,
+    ) -> anyhow::Result<(DownloadOutcome, ComputedHashes)> {
+        std::fs::remove_file(get_hasher_state_path(part_path)).ok();
+        verify_download(part_path, target_file, &computed)?;
+        if let Some(outcome) = self.quarantine_if_flagged(part_path, &final_path, model_version, target_file)? {
+            return Ok((outcome, computed));
+        }
+        persist_download(part_path, &final_path)?;
+        let outcome = DownloadOutcome::Downloaded {
+            path: final_path,
+            bytes,
+        };
+        Ok((outcome, computed))
+    }
+
+    /// Analyzes the file at `path` if it is a pickle, and moves it to the quarantine directory
+    /// of `model_version` under the file name of `final_path` if it imports anything outside the
+    /// allowlist. Returns the outcome to report in that case.
+    fn quarantine_if_flagged(
+        &self,
+        path: &Path,
+        final_path: &Path,
+        model_version: &ModelVersion,
+        target_file: &ResourceFile,
+    ) -> anyhow::Result<Option<DownloadOutcome>> {
+        if !is_pickle(target_file, path) {
+            return Ok(None);
+        }
+        let config = self.config.clone().unwrap_or_default();
+        let analysis = pickle::analyze_file(path, &config.pickle_allowlist);
+        let concern = match &analysis {
+            Ok(report) if report.is_safe() => return Ok(None),
+            Ok(report) => format!(
+                "it imports {}",
+                report.flagged.iter().cloned().collect::<Vec<_>>().join(", ")
+            ),
+            Err(e) => format!("it couldn't be analyzed: {e:#}"),
+        };
+        // Keep each version apart, so quarantining one file never replaces the evidence kept for
+        // another.
+        let quarantine_directory = get_quarantine_directory(&config, model_version);
+        std::fs::create_dir_all(&quarantine_directory)?;
+        let file_name = final_path.file_name().ok_or(anyhow!(
+            "'{}' has no file name",
+            final_path.to_string_lossy()
+        ))?;
+        let quarantine_path = quarantine_directory.join(file_name);
+        if quarantine_path.exists() {
+            // Never replace the evidence that is already there. Both files matched what Civitai
+            // reported for this version, so nothing is lost.
+            std::fs::remove_file(path)?;
+            warn!(path =? &quarantine_path, concern, "Download was already quarantined");
+        } else {
+            persist_download(path, &quarantine_path)?;
+            warn!(path =? &quarantine_path, concern, "Quarantined download");
+        }
+        let record = QuarantineRecord {
+            model_id: model_version.model_id,
+            model_version_id: model_version.id,
+            file_id: target_file.id,
+            source_url: target_file.download_url.clone(),
+            concern: concern.clone(),
+            report: analysis.ok(),
+        };
+        if let Err(e) = record.write(&quarantine_path) {
+            warn!(error =? e, "Failed to write quarantine record for {}", quarantine_path.to_string_lossy());
+        }
+        Ok(Some(DownloadOutcome::SkippedByPolicy {
+            reason: format!(
+                "Quarantined to '{}' as {}",
+                quarantine_path.to_string_lossy(),
+                concern
+            ),
+        }))
+    }
+
+    /// Decides whether to split a download into parallel byte ranges, returning the number of
+    /// connections to use along with the extra download slots they take up.
+    ///
+    /// Every connection past the first counts against `max_concurrent_downloads`, so a download
+    /// only uses as many as there are free slots.
+    fn get_segment_count(
+        &self,
+        response: &Response,
+        total_size: u64,
+    ) -> Option<(usize, OwnedSemaphorePermit)> {
+        let config = self.config.as_ref()?;
+        let connections = config.segmented_download_connections;
+        if connections < 2
+            || total_size < config.segmented_download_threshold_mb * 1024 * 1024
+            || total_size <= BLAKE3_BLOCK_SIZE
+        {
+            return None;
+        }
+        let accepts_ranges = response
+            .headers()
+            .get(ACCEPT_RANGES)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.eq_ignore_ascii_case("bytes"))
+            .unwrap_or(false);
+        if !accepts_ranges {
+            debug!(url =? response.url(), "Server does not accept ranges. Not segmenting download");
+            return None;
+        }
+        let Some((extra, permit)) = self.scheduler.try_acquire_extra_downloads(connections - 1)
+        else {
+            debug!(url =? response.url(), "No free download slots. Not segmenting download");
+            return None;
+        };
+        Some((1 + extra, permit))
+    }
 
-        while let Some(item) = stream.next().await {
-            let chunk = item.or(Err(anyhow!("Failed to read chunk from stream")))?;
-            file.write_all(&chunk)
-                .or(Err(anyhow!("Error while writing to file")))?;
-            let new = min(downloaded + (chunk.len() as u64), total_size);
-            downloaded = new;
-            pb.set_position(new)
+    /// Downloads `total_size` bytes into a preallocated file at `path` using up to `connections`
+    /// parallel range requests, reporting progress on a single bar.
+    ///
+    /// Segments start on BLAKE3 block boundaries and are hashed as they arrive, so the chaining
+    /// values of their blocks are returned in file order. Their progress is saved next to `path`,
+    /// so a segment that fails is fetched again from its last complete block while the others are
+    /// kept, both by retries and by the next run.
+    async fn fetch_segments(
+        &self,
+        url: &str,
+        path: &Path,
+        total_size: u64,
+        connections: usize,
+        pb: &ProgressBar,
+        throttle: &Throttle,
+    ) -> anyhow::Result<Vec<Vec<ChainingValue>>> {
+        let state_path = get_hasher_state_path(path);
+        let download = match SegmentedDownload::load(&state_path, total_size) {
+            Ok(download) if path.metadata().is_ok_and(|m| m.len() == total_size) => {
+                debug!(url, fetched = download.fetched(), "Resuming segmented download");
+                download
+            }
+            _ => {
+                File::create(path)
+                    .and_then(|f| f.set_len(total_size))
+                    .with_context(|| format!("Failed to create file '{}'", path.to_string_lossy()))?;
+                SegmentedDownload::new(total_size, connections)
+            }
+        };
+        debug!(url, total_size, connections, segments = download.segments.len(), "Starting segmented download");
+        pb.set_position(download.fetched());
+        let pending: Vec<_> = download
+            .segments
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| !s.done)
+            .map(|(index, _)| index)
+            .collect();
+        let download = Mutex::new(download);
+        let fetched = stream::iter(pending)
+            .map(|index| {
+                let download = &download;
+                self.retry_policy.run(url, move || {
+                    self.fetch_segment(url, path, download, index, pb, throttle)
+                })
+            })
+            .buffer_unordered(connections)
+            .try_collect::<Vec<()>>()
+            .await;
+        let download = download.into_inner().map_err(|_| anyhow!("Segment state was poisoned"))?;
+        if let Err(e) = fetched {
+            if let Err(e) = save_segments(&download, path) {
+                warn!(error =? e, "Failed to save segment state for {}", path.to_string_lossy());
+            }
+            return Err(e);
+        }
+        Ok(download.into_blocks())
+    }
+
+    /// Fetches what is missing of segment `index` of `download` into `path`, recording each block
+    /// as it completes.
+    async fn fetch_segment(
+        &self,
+        url: &str,
+        path: &Path,
+        download: &Mutex<SegmentedDownload>,
+        index: usize,
+        pb: &ProgressBar,
+        throttle: &Throttle,
+    ) -> anyhow::Result<()> {
+        let segment = download.lock().unwrap().segments[index].clone();
+        let (start, end) = (segment.position(), segment.end);
+        let response = self
+            .client
+            .get(url)
+            .header(RANGE, format!("bytes={start}-{end}"))
+            .send()
+            .await
+            .with_context(|| format!("Failed to GET range from '{}'", &url))?;
+        let response = check_response(response)?;
+        if response.status() != StatusCode::PARTIAL_CONTENT
+            || get_content_range_start(response.headers()) != Some(start)
+        {
+            return Err(anyhow!("'{}' did not honor range request for bytes {}-{}", url, start, end));
         }
 
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Failed to open file '{}'", path.to_string_lossy()))?;
+        file.seek(SeekFrom::Start(start))?;
+        let expected = end - start + 1;
+        let mut written: u64 = 0;
+        let mut hasher = Blake3Segment::resume(segment.start, segment.blocks);
+        let mut recorded = hasher.blocks().len();
+        let mut stream = response.bytes_stream();
+        let streamed: anyhow::Result<()> = async {
+            while let Some(item) = stream.next().await {
+                let chunk = item.context("Failed to read chunk from stream")?;
+                let len = min(chunk.len() as u64, expected - written);
+                file.write_all(&chunk[..len as usize])
+                    .context("Error while writing to file")?;
+                hasher.update(&chunk[..len as usize]);
+                written += len;
+                pb.inc(len);
+                throttle.consume(len).await;
+
+                let blocks = hasher.blocks();
+                if blocks.len() > recorded {
+                    let mut download = download.lock().unwrap();
+                    download.segments[index].blocks.extend_from_slice(&blocks[recorded..]);
+                    let interval_blocks = (HASHER_STATE_INTERVAL / BLAKE3_BLOCK_SIZE) as usize;
+                    if blocks.len() / interval_blocks > recorded / interval_blocks {
+                        if let Err(e) = save_segments(&download, path) {
+                            warn!(error =? e, "Failed to save segment state for {}", path.to_string_lossy());
+                        }
+                    }
+                    recorded = blocks.len();
+                }
+            }
+            if written < expected {
+                return Err(TransientError {
+                    message: format!(
+                        "Segment {}-{} of '{}' ended early ({}/{} bytes)",
+                        start, end, url, written, expected
+                    ),
+                    retry_after: None,
+                }
+                .into());
+            }
+            Ok(())
+        }
+        .await;
+        if streamed.is_err() {
+            // A retried segment picks up from its last complete block, so take back the progress
+            // past that.
+            let kept = (segment.start + recorded as u64 * BLAKE3_BLOCK_SIZE).saturating_sub(start);
+            pb.set_position(pb.position().saturating_sub(written.saturating_sub(kept)));
+            return streamed;
+        }
+        let mut download = download.lock().unwrap();
+        let segment = &mut download.segments[index];
+        segment.blocks = hasher.finish();
+        segment.done = true;
         Ok(())
     }
 }
 
+/// Flushes the segmented download at `path` and saves its progress next to it, so the saved
+/// state never covers bytes that could still be lost.
+fn save_segments(download: &SegmentedDownload, path: &Path) -> anyhow::Result<()> {
+    File::open(path)
+        .and_then(|f| f.sync_data())
+        .context("Failed to flush segmented download")?;
+    download.save(&get_hasher_state_path(path))
+}
+
+/// Verifies a finished download against the hashes Civitai reported for it, and checks that
+/// safetensors files are well-formed.
+///
+/// A file that fails verification is removed so the next run starts over instead of resuming
+/// from corrupt data.
+fn verify_download(
+    part_path: &Path,
+    file: &ResourceFile,
+    computed: &ComputedHashes,
+) -> anyhow::Result<()> {
+    let mut result = match file.hashes.as_ref() {
+        Some(hashes) => computed.verify(part_path, hashes).map(|_| ()),
+        None => Ok(()),
+    };
+    if result.is_ok() && is_safetensors(file) {
+        result = safetensors::inspect(part_path).map(|i| {
+            debug!(tensors = i.tensor_count, "Validated safetensors header");
+        });
+    }
+    if let Err(e) = &result {
+        if e.is::<HashMismatch>() || e.is::<InvalidSafetensors>() {
+            error!(error =? e, "Removing corrupt download");
+            std::fs::remove_file(part_path).ok();
+        }
+    }
+    result
+}
+
+/// Reads the model version from the `.civitai.info` sidecar of `path`, so identifying a file
+/// that was downloaded before doesn't take a lookup by hash. Sidecars that don't list a file
+/// with `sha256` are ignored, since the file may have been replaced since.
+fn read_sidecar_version(path: &Path, sha256: &str) -> Option<ModelVersion> {
+    let info_path = sidecar::get_civitai_info_path(path);
+    if !info_path.exists() {
+        return None;
+    }
+    let model_version = sidecar::read_civitai_info(&info_path)
+        .inspect_err(|e| warn!(error =? e, "Ignoring unreadable {}", info_path.to_string_lossy()))
+        .ok()?;
+    let lists_file = model_version.files.iter().flatten().any(|f| {
+        f.hashes
+            .as_ref()
+            .and_then(|h| h.sha256.as_deref())
+            .is_some_and(|h| h.eq_ignore_ascii_case(sha256))
+    });
+    if !lists_file {
+        debug!("{} doesn't list {}", info_path.to_string_lossy(), sha256);
+        return None;
+    }
+    debug!("Identified {} from {}", path.to_string_lossy(), info_path.to_string_lossy());
+    Some(model_version)
+}
+
+/// Whether `file`, downloaded to `path`, is a pickle: either Civitai says so, or it looks like a
+/// torch checkpoint. Extensions like `.bin` are also used for raw data, so they say nothing.
+fn is_pickle(file: &ResourceFile, path: &Path) -> bool {
+    matches!(
+        ModelFormat::from_str(&file.format.clone().unwrap_or_default()),
+        Ok(ModelFormat::PickleTensor)
+    ) || (!is_safetensors(file) && pickle::looks_like_checkpoint(path))
+}
+
+fn is_safetensors(file: &ResourceFile) -> bool {
+    matches!(
+        ModelFormat::from_str(&file.format.clone().unwrap_or_default()),
+        Ok(ModelFormat::SafeTensor)
+    ) || file.name.to_ascii_lowercase().ends_with(".safetensors")
+}
+
+/// Feeds the bytes already present in a partial download into `hasher` so the final hash covers
+/// every resumed segment.
+///
+/// Only the bytes written after the hasher state saved next to the partial download are read
+/// back, unless that state is missing or no longer fits the file.
+async fn prime_hasher(
+    hasher: StreamingHasher,
+    part_path: &Path,
+) -> anyhow::Result<StreamingHasher> {
+    let path = part_path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let length = path.metadata()?.len();
+        let state_path = get_hasher_state_path(&path);
+        let resumed = HasherState::load(&state_path).and_then(|state| {
+            if state.position > length {
+                return Err(anyhow!("Saved hasher state is past the end of the file"));
+            }
+            hasher.clone().resume(state)
+        });
+        let mut hasher = match resumed {
+            Ok(resumed) => resumed,
+            Err(e) => {
+                if state_path.exists() {
+                    debug!(error =? e, "Ignoring saved hasher state");
+                }
+                hasher
+            }
+        };
+        let from = hasher.position();
+        let read = hasher.update_from_file(&path)?;
+        debug!(part_path =? &path, from, read, "Hashed existing partial download");
+        Ok(hasher)
+    })
+    .await?
+}
+
+/// Appends `chunk` to a partial download and feeds it to `hasher`, saving the hasher state each
+/// time the download passes a multiple of [`HASHER_STATE_INTERVAL`] so resuming doesn't have to
+/// read everything back.
+fn write_part(
+    file: &mut File,
+    part_path: &Path,
+    hasher: &mut StreamingHasher,
+    mut chunk: &[u8],
+) -> anyhow::Result<()> {
+    while !chunk.is_empty() {
+        let until_state = HASHER_STATE_INTERVAL - hasher.position() % HASHER_STATE_INTERVAL;
+        let (now, later) = chunk.split_at(min(until_state, chunk.len() as u64) as usize);
+        file.write_all(now).context("Error while writing to file")?;
+        hasher.update(now);
+        chunk = later;
+        if !hasher.position().is_multiple_of(HASHER_STATE_INTERVAL) {
+            continue;
+        }
+        if let Some(state) = hasher.save_state() {
+            // The state must never cover bytes that could still be lost.
+            let saved = file
+                .sync_data()
+                .context("Failed to flush partial download")
+                .and_then(|_| state.save(&get_hasher_state_path(part_path)));
+            if let Err(e) = saved {
+                warn!(error =? e, "Failed to save hasher state for {}", part_path.to_string_lossy());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Flushes a finished download to disk and atomically moves it to its final name, so a file
+/// under the real model name is always complete.
+fn persist_download(part_path: &Path, final_path: &Path) -> anyhow::Result<()> {
+    File::open(part_path)
+        .and_then(|f| f.sync_all())
+        .with_context(|| format!("Failed to flush '{}' to disk", part_path.to_string_lossy()))?;
+    std::fs::rename(part_path, final_path).with_context(|| {
+        format!(
+            "Failed to move '{}' to '{}'",
+            part_path.to_string_lossy(),
+            final_path.to_string_lossy()
+        )
+    })?;
+    #[cfg(unix)]
+    if let Some(parent) = final_path.parent() {
+        // Persist the rename itself; not all platforms allow opening directories.
+        File::open(parent).and_then(|d| d.sync_all()).ok();
+    }
+    debug!(final_path =? final_path, "Persisted download");
+    Ok(())
+}
+
+/// Returns the path segmented downloads for `final_path` are assembled in.
+///
+/// This is kept apart from the `.part` file because a preallocated file can't be resumed.
+fn get_segmented_path(final_path: &Path) -> PathBuf {
+    let mut part = final_path.as_os_str().to_owned();
+    part.push(".segments.part");
+    PathBuf::from(part)
+}
+
+/// Builds the session cookie for `token`, scoped to the host of `api_base_url` and its
+/// subdomains.
+fn get_token_cookie(api_base_url: &str, token: &str) -> anyhow::Result<(Url, String)> {
+    let url = Url::parse(api_base_url)
+        .with_context(|| format!("'{}' is not a valid URL", api_base_url))?;
+    let mut cookie = format!("__Secure-civitai-token={token}; Path=/; HttpOnly; SameSite=Lax");
+    // IP addresses only take host-only cookies.
+    if let Some(domain) = url.domain() {
+        cookie.push_str(&format!("; Domain={domain}"));
+    }
+    if url.scheme() == "https" {
+        cookie.push_str("; Secure");
+    }
+    Ok((url, cookie))
+}
+
+/// Names a config after the checkpoint saved with the stem `stem`.
+fn get_config_file_name(stem: &str, config: &ResourceFile) -> String {
+    let extension = Path::new(&config.name)
+        .extension()
+        .map(|e| e.to_string_lossy().to_string())
+        .unwrap_or_else(|| "yaml".to_string());
+    format!("{stem}.{extension}")
+}
+
+/// Returns the path the hasher state of the partial download at `part_path` is saved to.
+fn get_hasher_state_path(part_path: &Path) -> PathBuf {
+    let mut path = part_path.as_os_str().to_owned();
+    path.push(".hash");
+    PathBuf::from(path)
+}
+
+/// Returns the directory files of `model_version` are quarantined in.
+fn get_quarantine_directory(config: &Config, model_version: &ModelVersion) -> PathBuf {
+    config
+        .quarantine_directory()
+        .join(model_version.model_id.to_string())
+        .join(model_version.id.to_string())
+}
+
+/// Returns the path partial downloads for `final_path` are written to before being renamed.
+fn get_part_path(final_path: &Path) -> PathBuf {
+    let mut part = final_path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// Parses the first byte position out of a `Content-Range: bytes <start>-<end>/<total>` header.
+fn get_content_range_start(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes ")?
+        .split('-')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Parses the total length out of a `Content-Range: bytes <start>-<end>/<total>` or
+/// `bytes */<total>` header.
+fn get_content_range_total(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .rsplit_once('/')?
+        .1
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// How often the hasher state of a partial download is saved. A multiple of
+/// [`hashing::BLAKE3_BLOCK_SIZE`], so BLAKE3 can always be picked up again.
+const HASHER_STATE_INTERVAL: u64 = 64 * 1024 * 1024;
+
 const MAIN_API_URL: &str = "https://civitai.com/api/v1";
@@ -1,22 +1,41 @@
+use std::fmt;
+use std::path::PathBuf;
 use std::process::exit;
+use std::str::FromStr;
+use std::time::Duration;
 
-use civitdl::Civit;
+use civitdl::throttle::parse_rate;
+use civitdl::model::model_version::{ModelVersion, NSFW};
+use civitdl::model::Model;
+use civitdl::hashing::{hash_file_all, HashKind};
+use civitdl::manifest::{relocate, Manifest};
+use civitdl::report::{DownloadOutcome, DownloadReport, Summary};
+use civitdl::pickle::PickleReport;
+use civitdl::safetensors::Inspection;
+use civitdl::scan::{find_model_files, ScanSummary};
+use civitdl::update::{get_installed_versions, Updates};
+use civitdl::plan::{Plan, PlannedAction, PlannedItem};
+use civitdl::preflight::{DiskSpaceReport, PlannedDownload};
+use civitdl::{parse_variant, Civit, FileSelection, ModelFormat, ResourceType};
 
-use clap::{arg, ArgAction, Parser};
+use clap::{ArgAction, Parser, Subcommand};
 
 use dotenvy::dotenv;
 use futures::future::join_all;
 
 use tracing::{debug, error, info, trace, warn};
-mod model;
 use civitdl::Config;
-use anyhow::anyhow;
+use anyhow::Context;
 
 use env_logger::Env;
+use serde::Serialize;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(short, long, long_help = "The IDs of the models to download", action=ArgAction::Append, num_args=1..)]
     ids: Vec<String>,
 
@@ -29,9 +48,425 @@ struct Args {
 
     #[arg(short, long, long_help = "The ID of the model version to download")]
     override_id: Option<String>,
+
+    #[arg(long, long_help = "The maximum number of files to download at the same time")]
+    max_concurrent_downloads: Option<usize>,
+
+    #[arg(long, long_help = "The maximum number of API requests to make at the same time")]
+    max_concurrent_requests: Option<usize>,
+
+    #[arg(long, value_parser = parse_rate, long_help = "The maximum combined download speed, e.g. 500K or 10M (bytes per second)")]
+    limit_rate: Option<u64>,
+
+    #[arg(long, value_parser = parse_rate, long_help = "The maximum speed of each individual download, e.g. 500K or 10M (bytes per second)")]
+    limit_rate_per_download: Option<u64>,
+
+    #[arg(long, long_help = "Download every file of each model version instead of only the preferred one")]
+    all_files: bool,
+
+    #[arg(long, value_delimiter = ',', value_parser = parse_variant::<ResourceType>, long_help = "Download every file of these types, e.g. Model,VAE,Config")]
+    file_types: Vec<ResourceType>,
+
+    #[arg(long, value_delimiter = ',', value_parser = parse_variant::<ModelFormat>, long_help = "Download every file in these formats, e.g. SafeTensor,PickleTensor")]
+    file_formats: Vec<ModelFormat>,
+
+    #[arg(long, long_help = "Save this many preview images next to each model as <name>.preview.png")]
+    previews: Option<usize>,
+
+    #[arg(long, value_parser = NSFW::from_str, long_help = "The most explicit preview images to save: None, Soft, Mature or X")]
+    max_preview_nsfw: Option<NSFW>,
+
+    #[arg(long, long_help = "Don't write metadata sidecars such as <name>.civitai.info next to downloads")]
+    no_sidecars: bool,
+
+    #[arg(long, long_help = "The Civitai API to use instead of https://civitai.com/api/v1")]
+    api_base_url: Option<String>,
+
+    #[arg(long, long_help = "Download files even if Civitai's pickle or virus scans didn't succeed. Pickle files can run code when loaded")]
+    allow_failed_scans: bool,
+
+    #[arg(long, value_delimiter = ',', long_help = "Globals, as module.name, that pickle files may import on top of the torch, collections and numpy ones needed to rebuild a state dict")]
+    pickle_allowlist: Vec<String>,
+
+    #[arg(long, long_help = "Where to move pickle files that import anything outside the allowlist. Defaults to <base directory>/quarantine")]
+    quarantine_directory: Option<PathBuf>,
+
+    #[arg(long, long_help = "Start downloading even if the target filesystems don't have enough free space")]
+    ignore_disk_space: bool,
+
+    #[arg(long, long_help = "Resolve everything that would be downloaded and print the plan without downloading anything")]
+    dry_run: bool,
+
+    #[arg(long, requires = "dry_run", long_help = "Print the dry-run plan as JSON")]
+    json: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check the downloaded models for newer versions
+    Update {
+        #[arg(long, long_help = "Download the newest version of every model that has an update")]
+        apply: bool,
+    },
+    /// Print every kind of hash Civitai uses for the given files
+    Hash {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Validate safetensors files and show their tensors, parameters and metadata, or list the
+    /// imports of pickle files
+    Inspect {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        #[arg(long, long_help = "Print the results as JSON")]
+        json: bool,
+    },
+    /// Convert the state dicts of PickleTensor checkpoints to .safetensors files next to them,
+    /// without running any of their code. Checkpoints must be saved by torch 1.6 or later, and
+    /// tensors in untyped storages need the dtype torch 2 saves with them
+    Convert {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        #[arg(long, long_help = "Remove each checkpoint once it has been converted, moving its manifest entry to the new file")]
+        replace: bool,
+
+        #[arg(long, long_help = "Replace .safetensors files that already exist")]
+        overwrite: bool,
+    },
+    /// Identify local model files by hash and record them as if civitdl had downloaded them
+    #[command(alias = "identify")]
+    Scan {
+        #[arg(long_help = "The files or directories to scan. Defaults to the Stable Diffusion base directory")]
+        paths: Vec<PathBuf>,
+    },
+}
+
+/// Reads an optional setting from the environment, ignoring values that fail to parse.
+fn parse_var<T: FromStr>(key: &str) -> Option<T> {
+    dotenvy::var(key).ok().and_then(|v| v.parse().ok())
+}
+
+/// Reads a comma separated list from the environment.
+fn parse_list(key: &str) -> Vec<String> {
+    dotenvy::var(key)
+        .map(|v| {
+            v.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Refuses to start a batch that won't fit on the target filesystems, unless told to ignore it.
+async fn check_disk_space(civit: &Civit, selections: &[(Model, Vec<ModelVersion>)], ignore: bool) {
+    let mut planned = Vec::new();
+    for (model, versions) in selections {
+        match civit.plan_downloads(model, versions).await {
+            Ok(p) => planned.extend(p),
+            Err(e) => warn!(error =? e, "Failed to plan downloads for {model:?}"),
+        }
+    }
+    match civitdl::preflight::check_disk_space(&planned) {
+        Ok(report) if !report.is_sufficient() => {
+            eprintln!("Not enough free disk space for this batch:\n{report}");
+            if ignore {
+                warn!("Ignoring insufficient disk space as requested");
+            } else {
+                eprintln!("Free up space or pass --ignore-disk-space to download anyway");
+                exit(1)
+            }
+        }
+        Ok(report) => debug!("Disk space check passed:\n{report}"),
+        Err(e) => warn!(error =? e, "Failed to check free disk space"),
+    }
+}
+
+/// Something that could not be resolved while planning a dry run.
+#[derive(Debug, Serialize)]
+struct PlanError {
+    model_id: String,
+    model_version_id: Option<i64>,
+    reason: String,
+}
+
+#[derive(Debug, Serialize)]
+struct PlanOutput {
+    items: Vec<PlannedItem>,
+    errors: Vec<PlanError>,
+    disk_space: Option<DiskSpaceReport>,
+}
+
+/// Resolves every selected version without downloading and prints the result, returning
+/// whether everything could be resolved.
+async fn print_plan(
+    civit: &Civit,
+    selections: &[(Model, Vec<ModelVersion>)],
+    mut errors: Vec<PlanError>,
+    json: bool,
+) -> bool {
+    let planned = join_all(selections.iter().flat_map(|(model, versions)| {
+        versions.iter().map(move |version| async move {
+            (model, version, civit.plan_download(model, version).await)
+        })
+    }))
+    .await;
+
+    let mut items = Vec::new();
+    for (model, version, result) in planned {
+        match result {
+            Ok(i) => items.extend(i),
+            Err(e) => errors.push(PlanError {
+                model_id: model.id.to_string(),
+                model_version_id: Some(version.id),
+                reason: format!("{e:#}"),
+            }),
+        }
+    }
+
+    let downloads = items
+        .iter()
+        .filter(|i| !matches!(i.action, PlannedAction::Blocked { .. }))
+        .map(PlannedDownload::from)
+        .collect::<Vec<_>>();
+    let disk_space = civitdl::preflight::check_disk_space(&downloads)
+        .inspect_err(|e| warn!(error =? e, "Failed to check free disk space"))
+        .ok();
+
+    let ok = errors.is_empty();
+    if json {
+        let output = PlanOutput {
+            items,
+            errors,
+            disk_space,
+        };
+        match serde_json::to_string_pretty(&output) {
+            Ok(o) => println!("{o}"),
+            Err(e) => error!(error =? e, "Failed to serialize plan"),
+        }
+    } else {
+        println!("{}", Plan(&items));
+        if let Some(report) = disk_space {
+            println!("Disk space:\n{report}");
+        }
+        for e in &errors {
+            match e.model_version_id {
+                Some(v) => eprintln!("Failed to plan version {} of model {}: {}", v, e.model_id, e.reason),
+                None => eprintln!("Failed to plan model {}: {}", e.model_id, e.reason),
+            }
+        }
+    }
+    ok
+}
+
+/// Lists the models in the manifest that have a newer version, downloading them if `apply` is
+/// set, or printing the plan instead when `dry_run` is also set. Returns the exit code.
+async fn update(civit: &Civit, apply: bool, dry_run: bool, json: bool, ignore_disk_space: bool) -> i32 {
+    let manifest_path = civit.config.clone().unwrap_or_default().manifest_path();
+    let manifest = match Manifest::load(&manifest_path) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("{e:#}");
+            return 1;
+        }
+    };
+    let installed = get_installed_versions(&manifest);
+    if installed.is_empty() {
+        println!("No downloads recorded in {}", manifest_path.to_string_lossy());
+        return 0;
+    }
+
+    let mut failed = false;
+    let mut updates = Vec::new();
+    for (model_id, result) in civit.check_for_updates(&installed).await {
+        match result {
+            Ok(Some(u)) => updates.push(u),
+            Ok(None) => debug!(model_id, "Up to date"),
+            Err(e) => {
+                eprintln!("Failed to check model {model_id} for updates: {e:#}");
+                failed = true;
+            }
+        }
+    }
+    // Keep JSON plans parseable.
+    if !(apply && json) {
+        println!("{}", Updates(&updates));
+    }
+
+    if apply && !updates.is_empty() {
+        let selections = updates
+            .iter()
+            .map(|u| (u.model.clone(), vec![u.latest.clone()]))
+            .collect::<Vec<_>>();
+        if dry_run {
+            let ok = print_plan(civit, &selections, Vec::new(), json).await;
+            return if ok && !failed { 0 } else { 1 };
+        }
+        check_disk_space(civit, &selections, ignore_disk_space).await;
+        let reports = join_all(
+            updates
+                .iter()
+                .map(|u| civit.clone().download_version(&u.latest, u.model.clone())),
+        )
+        .await
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+        let summary = Summary(&reports);
+        println!("\n{summary}");
+        if summary.has_hash_mismatches() {
+            return 2;
+        }
+        failed |= summary.has_failures();
+    }
+    if failed {
+        1
+    } else {
+        0
+    }
+}
+
+/// Prints every hash of each of `files`. Returns the exit code.
+fn hash(files: &[PathBuf]) -> i32 {
+    let mut code = 0;
+    for file in files {
+        match hash_file_all(file) {
+            Ok(hashes) => {
+                println!("{}", file.to_string_lossy());
+                for kind in HashKind::STRONGEST_FIRST {
+                    let value = hashes.get(kind).unwrap_or_default().to_uppercase();
+                    println!("  {:<7} {}", kind.as_ref(), value);
+                }
+            }
+            Err(e) => {
+                eprintln!("Failed to hash '{}': {e:#}", file.to_string_lossy());
+                code = 1;
+            }
+        }
+    }
+    code
 }
 
-#[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// Validates and summarizes each of `files`. Returns the exit code.
+fn inspect(files: &[PathBuf], json: bool, pickle_allowlist: &[String]) -> i32 {
+    let mut code = 0;
+    let mut inspections = Vec::new();
+    for file in files {
+        let is_safetensors = file
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("safetensors"));
+        let inspection = if is_safetensors {
+            civitdl::safetensors::inspect(file).map(Inspected::Safetensors)
+        } else {
+            civitdl::pickle::analyze_file(file, pickle_allowlist).map(|report| {
+                if !report.is_safe() {
+                    code = 1;
+                }
+                Inspected::Pickle {
+                    path: file.clone(),
+                    report,
+                }
+            })
+        };
+        match inspection {
+            Ok(i) => inspections.push(i),
+            Err(e) => {
+                eprintln!("{e:#}");
+                code = 1;
+            }
+        }
+    }
+    if json {
+        match serde_json::to_string_pretty(&inspections) {
+            Ok(o) => println!("{o}"),
+            Err(e) => error!(error =? e, "Failed to serialize inspections"),
+        }
+    } else {
+        for i in inspections {
+            println!("{i}");
+        }
+    }
+    code
+}
+
+/// Converts each of `files` to safetensors. Returns the exit code.
+fn convert(civit: &Civit, files: &[PathBuf], replace: bool, overwrite: bool) -> i32 {
+    let manifest_path = civit.config.clone().unwrap_or_default().manifest_path();
+    let mut code = 0;
+    for file in files {
+        // The manifest records canonical paths, which can't be resolved once the file is gone.
+        let source = file.canonicalize().unwrap_or(file.clone());
+        match civitdl::convert::convert(file, replace, overwrite) {
+            Ok(conversion) => {
+                println!("{conversion}");
+                if conversion.replaced {
+                    if let Err(e) = relocate(&manifest_path, &source, &conversion.target) {
+                        error!(error =? e, "Failed to update the manifest");
+                        code = 1;
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("Failed to convert '{}': {e:#}", file.to_string_lossy());
+                code = 1;
+            }
+        }
+    }
+    code
+}
+
+/// What `inspect` found in a file, depending on its format.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Inspected {
+    Safetensors(Inspection),
+    Pickle {
+        path: PathBuf,
+        #[serde(flatten)]
+        report: PickleReport,
+    },
+}
+
+impl fmt::Display for Inspected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Inspected::Safetensors(i) => write!(f, "{i}"),
+            Inspected::Pickle { path, report } => {
+                writeln!(f, "{}", path.to_string_lossy())?;
+                write!(f, "{report}")
+            }
+        }
+    }
+}
+
+/// Identifies the model files in `paths` and lists the ones that couldn't be. Returns the exit
+/// code.
+async fn scan(civit: &Civit, mut paths: Vec<PathBuf>) -> i32 {
+    let config = civit.config.clone().unwrap_or_default();
+    if paths.is_empty() {
+        paths.push(config.stable_diffusion_base_directory());
+    }
+    // Quarantined files must stay out of the library.
+    let files = find_model_files(&paths, &[config.quarantine_directory()]);
+    if files.is_empty() {
+        println!("No model files found in {paths:?}");
+        return 0;
+    }
+    info!("Scanning {} files", files.len());
+    let results = civit.scan_files(files).await;
+    let summary = ScanSummary(&results);
+    println!("{summary}");
+    if summary.has_failures() {
+        1
+    } else {
+        0
+    }
+}
+
+#[tokio::main(flavor = "multi_thread")]
 async fn main() {
     env_logger::Builder::from_env(Env::default().default_filter_or("warn")).init();
     let config_dir = civitdl::get_config_directory();
@@ -47,13 +482,6 @@ async fn main() {
     let args = Args::parse();
     let mut ids = args.ids;
 
-    if ids.is_empty() {
-        error!("No model ids provided! Exiting ...");
-        exit(1)
-    } else {
-        info!("Parsed IDs: {ids:?}");
-    }
-
     let config = match envy::from_env::<Config>() {
         Ok(parsed_config) => {
             debug!("Parsed config: {:#?}", &parsed_config);
@@ -72,7 +500,7 @@ async fn main() {
 
             trace!(model_format =? &model_format, resource_type =? &resource_type, stable_diffusion_base_directory =? &stable_diffusion_base_directory, stable_diffusion_fallback_directory =? &stable_diffusion_fallback_directory, api_key =? &api_key, token =? &token);
 
-            let conf = Config::new(
+            let mut conf = Config::new(
                 api_key,
                 token,
                 stable_diffusion_base_directory,
@@ -80,16 +508,151 @@ async fn main() {
                 model_format,
                 resource_type,
             );
+            if let Some(n) = parse_var("max_concurrent_downloads") {
+                conf = conf.with_max_concurrent_downloads(n);
+            }
+            if let Some(n) = parse_var("max_concurrent_requests") {
+                conf = conf.with_max_concurrent_requests(n);
+            }
+            let mut retry_policy = conf.retry_policy();
+            if let Some(n) = parse_var("retry_max_attempts") {
+                retry_policy.max_attempts = n;
+            }
+            if let Some(ms) = parse_var("retry_base_delay_ms") {
+                retry_policy.base_delay = Duration::from_millis(ms);
+            }
+            if let Some(ms) = parse_var("retry_max_delay_ms") {
+                retry_policy.max_delay = Duration::from_millis(ms);
+            }
+            if let Some(ms) = parse_var("retry_max_retry_after_ms") {
+                retry_policy.max_retry_after = Duration::from_millis(ms);
+            }
+            if let Some(jitter) = parse_var("retry_jitter") {
+                retry_policy.jitter = jitter;
+            }
+            conf = conf.with_retry_policy(retry_policy);
+            if let Some(n) = parse_var("segmented_download_connections") {
+                conf = conf.with_segmented_download_connections(n);
+            }
+            if let Some(mb) = parse_var("segmented_download_threshold_mb") {
+                conf = conf.with_segmented_download_threshold_mb(mb);
+            }
+            if let Some(verify) = parse_var("verify_segmented_downloads") {
+                conf = conf.with_verify_segmented_downloads(verify);
+            }
+            conf = conf
+                .with_max_bytes_per_second(parse_var("max_bytes_per_second"))
+                .with_max_bytes_per_second_per_download(parse_var("max_bytes_per_second_per_download"));
+            let file_types = parse_list("file_types");
+            let file_formats = parse_list("file_formats");
+            let file_selection = if parse_var("all_files").unwrap_or(false) {
+                FileSelection::All
+            } else {
+                FileSelection::parse(&file_types, &file_formats).unwrap_or_else(|e| {
+                    error!("{e:#}");
+                    exit(1)
+                })
+            };
+            conf = conf.with_file_selection(file_selection).with_previews(
+                parse_var("preview_images").unwrap_or_default(),
+                parse_var("preview_max_nsfw").unwrap_or_default(),
+            );
+            if let Some(write_sidecars) = parse_var("write_sidecars") {
+                conf = conf.with_write_sidecars(write_sidecars);
+            }
+            conf = conf
+                .with_manifest_path(parse_var("manifest_path"))
+                .with_api_base_url(parse_var("api_base_url"))
+                .with_allow_failed_scans(parse_var("allow_failed_scans").unwrap_or(false))
+                .with_pickle_allowlist(parse_list("pickle_allowlist"))
+                .with_quarantine_directory(parse_var("quarantine_directory"));
 
             debug!(config =? &conf);
             Some(conf)
         }
     };
 
+    let config = config.map(|mut c| {
+        if let Some(n) = args.max_concurrent_downloads {
+            c = c.with_max_concurrent_downloads(n);
+        }
+        if let Some(n) = args.max_concurrent_requests {
+            c = c.with_max_concurrent_requests(n);
+        }
+        if args.limit_rate.is_some() {
+            c = c.with_max_bytes_per_second(args.limit_rate);
+        }
+        if args.limit_rate_per_download.is_some() {
+            c = c.with_max_bytes_per_second_per_download(args.limit_rate_per_download);
+        }
+        if args.previews.is_some() || args.max_preview_nsfw.is_some() {
+            let preview_images = args.previews.unwrap_or(c.preview_images());
+            let preview_max_nsfw = args.max_preview_nsfw.clone().unwrap_or(c.preview_max_nsfw());
+            c = c.with_previews(preview_images, preview_max_nsfw);
+        }
+        if args.api_base_url.is_some() {
+            c = c.with_api_base_url(args.api_base_url.clone());
+        }
+        if args.allow_failed_scans {
+            c = c.with_allow_failed_scans(true);
+        }
+        if !args.pickle_allowlist.is_empty() {
+            c = c.with_pickle_allowlist(args.pickle_allowlist.clone());
+        }
+        if args.quarantine_directory.is_some() {
+            c = c.with_quarantine_directory(args.quarantine_directory.clone());
+        }
+        if args.no_sidecars {
+            c = c.with_write_sidecars(false);
+        }
+        if args.all_files {
+            c = c.with_file_selection(FileSelection::All);
+        } else if !args.file_types.is_empty() || !args.file_formats.is_empty() {
+            c = c.with_file_selection(FileSelection::Matching {
+                resource_types: args.file_types.clone(),
+                model_formats: args.file_formats.clone(),
+            });
+        }
+        c
+    });
+
+    if let Some(Err(e)) = config.as_ref().map(Config::file_selection) {
+        error!("{e:#}");
+        exit(1)
+    }
+
     let all = args.all;
 
     let civit = Civit::new(config);
+
+    if let Some(command) = args.command {
+        let code = match command {
+            Command::Update { apply } => {
+                update(&civit, apply, args.dry_run, args.json, args.ignore_disk_space).await
+            }
+            Command::Scan { paths } => scan(&civit, paths).await,
+            Command::Convert {
+                files,
+                replace,
+                overwrite,
+            } => convert(&civit, &files, replace, overwrite),
+            Command::Hash { files } => hash(&files),
+            Command::Inspect { files, json } => {
+                let pickle_allowlist = civit.config.clone().unwrap_or_default().pickle_allowlist();
+                inspect(&files, json, &pickle_allowlist)
+            }
+        };
+        exit(code)
+    }
+
+    if ids.is_empty() {
+        error!("No model ids provided! Exiting ...");
+        exit(1)
+    } else {
+        info!("Parsed IDs: {ids:?}");
+    }
     let mut res = Vec::new();
+    let mut reports = Vec::new();
     let override_id = args.override_id;
 
     if let Some(oid) = override_id {
@@ -101,14 +664,38 @@ async fn main() {
             .clone()
             .get_model_details(id.clone())
             .await
-            .or({
-                Err(anyhow!(format!("Failed to get model details for model {model_id:?}")))
-            }).unwrap_or_default();
+            .with_context(|| format!("Failed to get model details for model {model_id:?}"));
 
-        civit_client
-            .download_specific_resource_for_model(model, oid)
-            .await
-            .unwrap_or_default();
+        match model {
+            Ok(model) => {
+                let versions = model
+                    .model_versions
+                    .iter()
+                    .filter(|v| v.id.to_string().eq(&oid))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if args.dry_run {
+                    let errors = match versions.is_empty() {
+                        true => vec![PlanError {
+                            model_id: model.id.to_string(),
+                            model_version_id: oid.parse().ok(),
+                            reason: format!("Failed to find model version {} for model {}", oid, model.id),
+                        }],
+                        false => Vec::new(),
+                    };
+                    let ok = print_plan(&civit, &[(model, versions)], errors, args.json).await;
+                    exit(if ok { 0 } else { 1 })
+                }
+                check_disk_space(&civit, &[(model.clone(), versions)], args.ignore_disk_space).await;
+
+                reports.extend(
+                    civit_client
+                        .download_specific_resource_for_model(model, oid)
+                        .await,
+                );
+            }
+            Err(e) => reports.push(DownloadReport::for_model_id(&model_id, DownloadOutcome::failed(&e))),
+        }
     } else {
         let results: Vec<anyhow::Result<civitdl::model::Model>> = join_all(
             ids.iter_mut()
@@ -119,9 +706,7 @@ async fn main() {
                     civit_client
                         .get_model_details(id.clone())
                         .await
-                        .or({
-                            Err(anyhow!(format!("Failed to get model details for model {model_id:?}")))
-                        })
+                        .with_context(|| format!("Failed to get model details for model {model_id:?}"))
                 })
                 .collect::<Vec<_>>(),
         )
@@ -129,7 +714,42 @@ async fn main() {
 
         res.extend(results);
 
-        join_all(
+        reports.extend(
+            ids.iter()
+                .zip(&res)
+                .filter_map(|(id, m)| m.as_ref().err().map(|e| (id, e)))
+                .map(|(id, e)| DownloadReport::for_model_id(id, DownloadOutcome::failed(e))),
+        );
+
+        let selections = res
+            .iter()
+            .filter_map(|m| m.as_ref().ok())
+            .map(|model| {
+                let versions = match all {
+                    true => model.model_versions.clone(),
+                    false => model.model_versions.iter().take(1).cloned().collect(),
+                };
+                (model.clone(), versions)
+            })
+            .collect::<Vec<_>>();
+        if args.dry_run {
+            let errors = reports
+                .iter()
+                .map(|r| PlanError {
+                    model_id: r.model_id.clone(),
+                    model_version_id: r.model_version_id,
+                    reason: match &r.outcome {
+                        DownloadOutcome::Failed { reason } => reason.clone(),
+                        other => format!("{other:?}"),
+                    },
+                })
+                .collect();
+            let ok = print_plan(&civit, &selections, errors, args.json).await;
+            exit(if ok { 0 } else { 1 })
+        }
+        check_disk_space(&civit, &selections, args.ignore_disk_space).await;
+
+        let downloaded = join_all(
             res.iter()
                 .map(|m|m.as_ref().ok())
                 .filter(|i|i.is_some())
@@ -143,5 +763,16 @@ async fn main() {
                 .collect::<Vec<_>>(),
         )
         .await;
+        reports.extend(downloaded.into_iter().flatten());
+    }
+
+    let summary = Summary(&reports);
+    println!("\n{summary}");
+    // Tell corrupt or tampered files apart from other failures.
+    if summary.has_hash_mismatches() {
+        exit(2)
+    }
+    if summary.has_failures() {
+        exit(1)
     }
 }
@@ -0,0 +1,251 @@
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::hashing::{hash_file_all, ComputedHashes};
+use crate::model::model_version::{Hashes, ModelVersion, ResourceFile};
+use crate::model::Model;
+
+const MANIFEST_VERSION: u32 = 1;
+
+/// A file civitdl put into the library.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub model_id: i64,
+    pub model_name: String,
+    pub model_version_id: i64,
+    pub model_version_name: String,
+    pub file_id: i64,
+    pub hashes: Hashes,
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    /// Seconds since the Unix epoch.
+    pub downloaded_at: u64,
+    pub source_url: String,
+    /// The file this one was converted from, as downloaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub converted_from: Option<ConvertedFrom>,
+}
+
+/// The original of a converted file, whose hashes are the ones Civitai knows it by.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConvertedFrom {
+    pub path: PathBuf,
+    pub hashes: Hashes,
+}
+
+impl ManifestEntry {
+    pub fn new(
+        model: &Model,
+        model_version: &ModelVersion,
+        file: &ResourceFile,
+        path: &Path,
+    ) -> anyhow::Result<Self> {
+        let size_bytes = path
+            .metadata()
+            .with_context(|| format!("Failed to read metadata of '{}'", path.to_string_lossy()))?
+            .len();
+        Ok(ManifestEntry {
+            model_id: model.id,
+            model_name: model.name.clone(),
+            model_version_id: model_version.id,
+            model_version_name: model_version.name.clone(),
+            file_id: file.id,
+            hashes: file.hashes.clone().unwrap_or_default(),
+            path: path.canonicalize().unwrap_or(path.to_path_buf()),
+            size_bytes,
+            downloaded_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            source_url: file.download_url.clone(),
+            converted_from: None,
+        })
+    }
+
+    /// Records the hashes the file was verified with over the ones Civitai reported, keeping the
+    /// reported ones that weren't computed.
+    pub fn with_computed_hashes(mut self, computed: ComputedHashes) -> Self {
+        let computed = Hashes::from(computed);
+        let reported = self.hashes;
+        self.hashes = Hashes {
+            auto_v1: computed.auto_v1.or(reported.auto_v1),
+            auto_v2: computed.auto_v2.or(reported.auto_v2),
+            sha256: computed.sha256.or(reported.sha256),
+            crc32: computed.crc32.or(reported.crc32),
+            blake3: computed.blake3.or(reported.blake3),
+        };
+        self
+    }
+}
+
+/// Everything civitdl has downloaded, stored as JSON in the config directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Manifest {
+            version: MANIFEST_VERSION,
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl Manifest {
+    /// Reads the manifest at `path`, which is empty if it doesn't exist yet.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Manifest::default());
+        }
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read manifest '{}'", path.to_string_lossy()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse manifest '{}'", path.to_string_lossy()))
+    }
+
+    /// Writes the manifest to `path` through a temporary file.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+        fs::write(&temp_path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write '{}'", temp_path.to_string_lossy()))?;
+        fs::rename(&temp_path, path)
+            .with_context(|| format!("Failed to move manifest to '{}'", path.to_string_lossy()))
+    }
+
+    /// Adds `entry`, replacing whatever was recorded for the same path before. When it is the
+    /// same file, it keeps the time it was first downloaded.
+    pub fn upsert(&mut self, mut entry: ManifestEntry) {
+        match self.entries.iter_mut().find(|e| e.path == entry.path) {
+            Some(existing) => {
+                if existing.file_id == entry.file_id {
+                    entry.downloaded_at = existing.downloaded_at;
+                }
+                *existing = entry;
+            }
+            None => self.entries.push(entry),
+        }
+    }
+
+    pub fn find_by_path(&self, path: &Path) -> Option<&ManifestEntry> {
+        let path = path.canonicalize().unwrap_or(path.to_path_buf());
+        self.entries.iter().find(|e| e.path == path)
+    }
+
+    pub fn find_by_model_id(&self, model_id: i64) -> impl Iterator<Item = &ManifestEntry> {
+        self.entries.iter().filter(move |e| e.model_id == model_id)
+    }
+}
+
+pub fn get_default_manifest_path() -> PathBuf {
+    crate::get_config_directory().join("manifest.json")
+}
+
+/// Adds `entry` to the manifest at `path`.
+///
+/// The manifest is re-read under an exclusive lock, so concurrent downloads and other civitdl
+/// processes don't lose each other's entries.
+pub fn record(path: &Path, entry: ManifestEntry) -> anyhow::Result<()> {
+    let _lock = lock(path)?;
+    let mut manifest = Manifest::load(path)?;
+    debug!(path =? &entry.path, "Recording download in manifest");
+    manifest.upsert(entry);
+    manifest.save(path)
+}
+
+/// Points the entry recorded for `from` at `to`, for a file that was replaced by a converted
+/// copy. The entry gets the hashes of the copy, and keeps the original's under `converted_from`
+/// as they still identify the file on Civitai.
+///
+/// Returns whether there was an entry for `from`.
+pub fn relocate(path: &Path, from: &Path, to: &Path) -> anyhow::Result<bool> {
+    let to = to.canonicalize().unwrap_or(to.to_path_buf());
+    let size_bytes = to.metadata()?.len();
+    // Hashing takes a while, so do it before taking the lock.
+    let hashes = hash_file_all(&to)?.into();
+    let _lock = lock(path)?;
+    let mut manifest = Manifest::load(path)?;
+    let Some(entry) = manifest.entries.iter_mut().find(|e| e.path == from) else {
+        return Ok(false);
+    };
+    debug!(from =? from, to =? &to, "Relocating manifest entry");
+    let original_hashes = std::mem::replace(&mut entry.hashes, hashes);
+    entry.converted_from = Some(ConvertedFrom {
+        path: from.to_path_buf(),
+        hashes: original_hashes,
+    });
+    entry.path = to;
+    entry.size_bytes = size_bytes;
+    manifest.save(path)?;
+    Ok(true)
+}
+
+/// Holds an exclusive lock on `<manifest>.lock` until the returned file is dropped.
+pub fn lock(path: &Path) -> anyhow::Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let lock_file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("Failed to open '{}'", Path::new(&lock_path).to_string_lossy()))?;
+    lock_file
+        .lock_exclusive()
+        .context("Failed to lock the manifest")?;
+    Ok(lock_file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_reported_hashes_that_were_not_computed() {
+        let entry = ManifestEntry {
+            model_id: 1,
+            model_name: "Model".to_string(),
+            model_version_id: 2,
+            model_version_name: "v1".to_string(),
+            file_id: 3,
+            hashes: Hashes {
+                auto_v1: Some("31FFF905".to_string()),
+                auto_v2: Some("0000000000".to_string()),
+                sha256: Some("00".repeat(32)),
+                crc32: Some("900FC85F".to_string()),
+                blake3: None,
+            },
+            path: PathBuf::from("model.safetensors"),
+            size_bytes: 0,
+            downloaded_at: 0,
+            source_url: String::new(),
+            converted_from: None,
+        };
+        let sha256 = "e027f527e307aaa32b1f3a7bd9ffda6d6c97de74f138df8e387b1cc4649c2c50";
+        let entry = entry.with_computed_hashes(ComputedHashes {
+            sha256: Some(sha256.to_string()),
+            ..Default::default()
+        });
+        assert_eq!(entry.hashes.sha256, Some(sha256.to_uppercase()));
+        assert_eq!(entry.hashes.auto_v2, Some(sha256[..10].to_uppercase()));
+        assert_eq!(entry.hashes.auto_v1.as_deref(), Some("31FFF905"));
+        assert_eq!(entry.hashes.crc32.as_deref(), Some("900FC85F"));
+        assert_eq!(entry.hashes.blake3, None);
+    }
+}
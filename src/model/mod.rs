@@ -1,4 +1,4 @@
-pub(crate) mod model_version;
+pub mod model_version;
 
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
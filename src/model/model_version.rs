@@ -1,4 +1,8 @@
 use serde_derive::{Deserialize, Serialize};
+use strum::{AsRefStr, EnumString};
+
+/// The result Civitai reports for a scan that found nothing.
+pub const SCAN_SUCCESS: &str = "Success";
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
@@ -30,6 +34,11 @@ pub struct ModelVersion {
     pub trained_words: Vec<String>,
     pub base_model: Option<String>,
     pub early_access_time_frame: Option<i64>,
+    /// `Published` once the version is out, or e.g. `Draft` or `Scheduled` before then.
+    pub status: Option<String>,
+    /// `Public`, `EarlyAccess` or `Private`.
+    pub availability: Option<String>,
+    pub published_at: Option<String>,
     pub description: Option<String>,
     pub files: Option<Vec<ResourceFile>>,
     pub images: Option<Vec<Image>>,
@@ -55,6 +64,39 @@ pub struct ResourceFile {
     pub download_url: String,
 }
 
+impl ModelVersion {
+    /// Whether everyone can download this version: it is published and neither in early access
+    /// nor private. Versions from before Civitai reported either are taken to be available.
+    pub fn is_available(&self) -> bool {
+        self.status.as_deref().is_none_or(|s| s.eq_ignore_ascii_case("Published"))
+            && self.availability.as_deref().is_none_or(|a| a.eq_ignore_ascii_case("Public"))
+    }
+}
+
+impl ResourceFile {
+    /// The size Civitai reported for this file, in bytes.
+    pub fn size_bytes(&self) -> Option<u64> {
+        self.size_kb.map(|kb| (kb * 1024.0).round() as u64)
+    }
+
+    /// Describes which of Civitai's pickle and virus scans didn't succeed, or `None` if both did.
+    pub fn get_failed_scans(&self) -> Option<String> {
+        let status = |result: &Option<String>| result.clone().unwrap_or("Not scanned".to_string());
+        let mut failures = Vec::new();
+        if self.pickle_scan_result.as_deref() != Some(SCAN_SUCCESS) {
+            let mut failure = format!("pickle scan: {}", status(&self.pickle_scan_result));
+            if let Some(message) = self.pickle_scan_message.as_deref().filter(|m| !m.trim().is_empty()) {
+                failure.push_str(&format!(" ({})", message.trim()));
+            }
+            failures.push(failure);
+        }
+        if self.virus_scan_result.as_deref() != Some(SCAN_SUCCESS) {
+            failures.push(format!("virus scan: {}", status(&self.virus_scan_result)));
+        }
+        (!failures.is_empty()).then(|| failures.join("; "))
+    }
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Hashes {
@@ -70,8 +112,12 @@ pub struct Hashes {
     pub blake3: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// How explicit an image is, from least to most.
+#[derive(
+    Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, EnumString, AsRefStr,
+)]
 pub enum NSFW {
+    #[default]
     None,
     Soft,
     Mature,
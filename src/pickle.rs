@@ -0,0 +1,1207 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+/// Pickle opcodes, as named by Python's `pickletools`.
+pub mod op {
+    pub const MARK: u8 = b'(';
+    pub const STOP: u8 = b'.';
+    pub const POP: u8 = b'0';
+    pub const POP_MARK: u8 = b'1';
+    pub const DUP: u8 = b'2';
+    pub const FLOAT: u8 = b'F';
+    pub const INT: u8 = b'I';
+    pub const BININT: u8 = b'J';
+    pub const BININT1: u8 = b'K';
+    pub const LONG: u8 = b'L';
+    pub const BININT2: u8 = b'M';
+    pub const NONE: u8 = b'N';
+    pub const PERSID: u8 = b'P';
+    pub const BINPERSID: u8 = b'Q';
+    pub const REDUCE: u8 = b'R';
+    pub const STRING: u8 = b'S';
+    pub const BINSTRING: u8 = b'T';
+    pub const SHORT_BINSTRING: u8 = b'U';
+    pub const UNICODE: u8 = b'V';
+    pub const BINUNICODE: u8 = b'X';
+    pub const APPEND: u8 = b'a';
+    pub const BUILD: u8 = b'b';
+    pub const GLOBAL: u8 = b'c';
+    pub const DICT: u8 = b'd';
+    pub const EMPTY_DICT: u8 = b'}';
+    pub const APPENDS: u8 = b'e';
+    pub const GET: u8 = b'g';
+    pub const BINGET: u8 = b'h';
+    pub const INST: u8 = b'i';
+    pub const LONG_BINGET: u8 = b'j';
+    pub const LIST: u8 = b'l';
+    pub const EMPTY_LIST: u8 = b']';
+    pub const OBJ: u8 = b'o';
+    pub const PUT: u8 = b'p';
+    pub const BINPUT: u8 = b'q';
+    pub const LONG_BINPUT: u8 = b'r';
+    pub const SETITEM: u8 = b's';
+    pub const TUPLE: u8 = b't';
+    pub const EMPTY_TUPLE: u8 = b')';
+    pub const SETITEMS: u8 = b'u';
+    pub const BINFLOAT: u8 = b'G';
+    pub const PROTO: u8 = 0x80;
+    pub const NEWOBJ: u8 = 0x81;
+    pub const EXT1: u8 = 0x82;
+    pub const EXT2: u8 = 0x83;
+    pub const EXT4: u8 = 0x84;
+    pub const TUPLE1: u8 = 0x85;
+    pub const TUPLE2: u8 = 0x86;
+    pub const TUPLE3: u8 = 0x87;
+    pub const NEWTRUE: u8 = 0x88;
+    pub const NEWFALSE: u8 = 0x89;
+    pub const LONG1: u8 = 0x8a;
+    pub const LONG4: u8 = 0x8b;
+    pub const BINBYTES: u8 = b'B';
+    pub const SHORT_BINBYTES: u8 = b'C';
+    pub const SHORT_BINUNICODE: u8 = 0x8c;
+    pub const BINUNICODE8: u8 = 0x8d;
+    pub const BINBYTES8: u8 = 0x8e;
+    pub const EMPTY_SET: u8 = 0x8f;
+    pub const ADDITEMS: u8 = 0x90;
+    pub const FROZENSET: u8 = 0x91;
+    pub const NEWOBJ_EX: u8 = 0x92;
+    pub const STACK_GLOBAL: u8 = 0x93;
+    pub const MEMOIZE: u8 = 0x94;
+    pub const FRAME: u8 = 0x95;
+    pub const BYTEARRAY8: u8 = 0x96;
+    pub const NEXT_BUFFER: u8 = 0x97;
+    pub const READONLY_BUFFER: u8 = 0x98;
+}
+
+/// The globals a plain state dict needs to be rebuilt: tensors, storages and ordered dicts.
+pub const SAFE_GLOBALS: &[&str] = &[
+    "collections.OrderedDict",
+    "torch._utils._rebuild_tensor",
+    "torch._utils._rebuild_tensor_v2",
+    "torch._utils._rebuild_tensor_v3",
+    "torch._utils._rebuild_parameter",
+    "torch._utils._rebuild_parameter_with_state",
+    "torch._utils._rebuild_qtensor",
+    "torch._tensor._rebuild_from_type_v2",
+    "torch.Size",
+    "torch.BFloat16Storage",
+    "torch.BoolStorage",
+    "torch.ByteStorage",
+    "torch.CharStorage",
+    "torch.DoubleStorage",
+    "torch.FloatStorage",
+    "torch.HalfStorage",
+    "torch.IntStorage",
+    "torch.LongStorage",
+    "torch.ShortStorage",
+    "torch.UntypedStorage",
+    "torch.storage.UntypedStorage",
+    "torch.bfloat16",
+    "torch.bool",
+    "torch.float16",
+    "torch.float32",
+    "torch.float64",
+    "torch.float8_e4m3fn",
+    "torch.float8_e5m2",
+    "torch.int8",
+    "torch.int16",
+    "torch.int32",
+    "torch.int64",
+    "torch.uint8",
+    "torch.uint16",
+    "torch.uint32",
+    "torch.uint64",
+    "numpy.core.multiarray._reconstruct",
+    "numpy._core.multiarray._reconstruct",
+    "numpy.core.multiarray.scalar",
+    "numpy._core.multiarray.scalar",
+    "numpy.ndarray",
+    "numpy.dtype",
+    "_codecs.encode",
+];
+
+/// The argument of a single pickle instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    None,
+    Int(i64),
+    /// A `LONG1`/`LONG4` little-endian two's complement integer.
+    Long(Vec<u8>),
+    Float(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+    /// The decimal text of `INT`, `LONG`, `FLOAT`, `GET` and `PUT`, and the quoted text of
+    /// `STRING`.
+    Line(String),
+    Global { module: String, name: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub opcode: u8,
+    pub arg: Arg,
+}
+
+/// Reads pickle instructions one at a time without executing anything.
+pub struct OpReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> OpReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        OpReader { data, position: 0 }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    fn take(&mut self, length: usize) -> anyhow::Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|end| *end <= self.data.len())
+            .ok_or(anyhow!("Pickle ends inside an instruction at byte {}", self.position))?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn take_uint(&mut self, width: usize) -> anyhow::Result<u64> {
+        let mut bytes = [0u8; 8];
+        bytes[..width].copy_from_slice(self.take(width)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn take_len(&mut self, width: usize) -> anyhow::Result<&'a [u8]> {
+        let length = usize::try_from(self.take_uint(width)?)?;
+        self.take(length)
+    }
+
+    fn take_line(&mut self) -> anyhow::Result<String> {
+        let rest = &self.data[self.position..];
+        let end = rest
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or(anyhow!("Unterminated line at byte {}", self.position))?;
+        let line = String::from_utf8_lossy(&rest[..end]).to_string();
+        self.position += end + 1;
+        Ok(line)
+    }
+
+    fn take_str(&mut self, width: usize) -> anyhow::Result<String> {
+        Ok(String::from_utf8(self.take_len(width)?.to_vec())?)
+    }
+
+    /// Reads the next instruction, or `None` at the end of the data.
+    pub fn read(&mut self) -> anyhow::Result<Option<Instruction>> {
+        if self.position >= self.data.len() {
+            return Ok(None);
+        }
+        let opcode = self.take(1)?[0];
+        let arg = match opcode {
+            op::MARK | op::STOP | op::POP | op::POP_MARK | op::DUP | op::NONE | op::BINPERSID
+            | op::REDUCE | op::APPEND | op::BUILD | op::DICT | op::EMPTY_DICT | op::APPENDS
+            | op::LIST | op::EMPTY_LIST | op::OBJ | op::SETITEM | op::TUPLE | op::EMPTY_TUPLE
+            | op::SETITEMS | op::NEWOBJ | op::TUPLE1 | op::TUPLE2 | op::TUPLE3 | op::NEWTRUE
+            | op::NEWFALSE | op::EMPTY_SET | op::ADDITEMS | op::FROZENSET | op::NEWOBJ_EX
+            | op::STACK_GLOBAL | op::MEMOIZE | op::NEXT_BUFFER | op::READONLY_BUFFER => Arg::None,
+            op::FLOAT | op::INT | op::LONG | op::PERSID | op::STRING | op::GET | op::PUT => {
+                Arg::Line(self.take_line()?)
+            }
+            op::UNICODE => Arg::Str(self.take_line()?),
+            op::GLOBAL | op::INST => Arg::Global {
+                module: self.take_line()?,
+                name: self.take_line()?,
+            },
+            op::BININT => Arg::Int(i32::from_le_bytes(self.take(4)?.try_into()?) as i64),
+            op::BININT1 | op::BINGET | op::BINPUT | op::PROTO | op::EXT1 => {
+                Arg::Int(self.take_uint(1)? as i64)
+            }
+            op::BININT2 | op::EXT2 => Arg::Int(self.take_uint(2)? as i64),
+            op::LONG_BINGET | op::LONG_BINPUT | op::EXT4 => Arg::Int(self.take_uint(4)? as i64),
+            op::FRAME => Arg::Int(self.take_uint(8)? as i64),
+            op::BINFLOAT => Arg::Float(f64::from_be_bytes(self.take(8)?.try_into()?)),
+            op::LONG1 => Arg::Long(self.take_len(1)?.to_vec()),
+            op::LONG4 => Arg::Long(self.take_len(4)?.to_vec()),
+            op::SHORT_BINUNICODE => Arg::Str(self.take_str(1)?),
+            op::BINUNICODE => Arg::Str(self.take_str(4)?),
+            op::BINUNICODE8 => Arg::Str(self.take_str(8)?),
+            op::SHORT_BINSTRING | op::SHORT_BINBYTES => Arg::Bytes(self.take_len(1)?.to_vec()),
+            op::BINSTRING | op::BINBYTES => Arg::Bytes(self.take_len(4)?.to_vec()),
+            op::BINBYTES8 | op::BYTEARRAY8 => Arg::Bytes(self.take_len(8)?.to_vec()),
+            other => {
+                return Err(anyhow!(
+                    "Unknown pickle opcode 0x{other:02x} at byte {}",
+                    self.position - 1
+                ))
+            }
+        };
+        Ok(Some(Instruction { opcode, arg }))
+    }
+}
+
+/// What [`analyze`] found in one or more pickles.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PickleReport {
+    /// Every global the pickles import, as `module.name`.
+    pub imports: BTreeSet<String>,
+    /// Imports outside the allowlist, and anything else that could run code.
+    pub flagged: BTreeSet<String>,
+}
+
+impl PickleReport {
+    pub fn is_safe(&self) -> bool {
+        self.flagged.is_empty()
+    }
+
+    fn merge(&mut self, other: PickleReport) {
+        self.imports.extend(other.imports);
+        self.flagged.extend(other.flagged);
+    }
+}
+
+impl fmt::Display for PickleReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "  Imports:")?;
+        for import in &self.imports {
+            let marker = if self.flagged.contains(import) { "!" } else { " " };
+            writeln!(f, "  {marker} {import}")?;
+        }
+        for flagged in self.flagged.difference(&self.imports) {
+            writeln!(f, "  ! {flagged}")?;
+        }
+        if self.is_safe() {
+            writeln!(f, "  Nothing outside the allowlist")
+        } else {
+            writeln!(f, "  {} flagged", self.flagged.len())
+        }
+    }
+}
+
+/// What the analysis knows about a value on the pickle stack.
+#[derive(Debug, Clone, PartialEq)]
+enum StackItem {
+    Mark,
+    Str(String),
+    Other,
+}
+
+/// Walks a single pickle up to its `STOP`, recording the globals it would import.
+///
+/// The stack is tracked just closely enough to resolve the strings `STACK_GLOBAL` takes its
+/// module and name from. Returns the report and the number of bytes the pickle spans.
+pub fn analyze(data: &[u8], allowed: &[&str]) -> anyhow::Result<(PickleReport, usize)> {
+    let mut report = PickleReport::default();
+    let mut stack: Vec<StackItem> = Vec::new();
+    let mut memo: HashMap<i64, StackItem> = HashMap::new();
+    let mut reader = OpReader::new(data);
+
+    let pop = |stack: &mut Vec<StackItem>, count: usize| -> anyhow::Result<Vec<StackItem>> {
+        let at = stack
+            .len()
+            .checked_sub(count)
+            .ok_or(anyhow!("Pickle stack underflow"))?;
+        Ok(stack.split_off(at))
+    };
+    let pop_mark = |stack: &mut Vec<StackItem>| -> anyhow::Result<()> {
+        let at = stack
+            .iter()
+            .rposition(|i| *i == StackItem::Mark)
+            .ok_or(anyhow!("Pickle has no mark to pop to"))?;
+        stack.truncate(at);
+        Ok(())
+    };
+    let import = |report: &mut PickleReport, module: &str, name: &str| {
+        let global = format!("{module}.{name}");
+        if !allowed.contains(&global.as_str()) {
+            report.flagged.insert(global.clone());
+        }
+        report.imports.insert(global);
+    };
+
+    while let Some(instruction) = reader.read()? {
+        match (instruction.opcode, instruction.arg) {
+            (op::STOP, _) => {
+                pop(&mut stack, 1)?;
+                return Ok((report, reader.position()));
+            }
+            (op::MARK, _) => stack.push(StackItem::Mark),
+            (op::POP, _) => {
+                pop(&mut stack, 1)?;
+            }
+            (op::POP_MARK, _) => pop_mark(&mut stack)?,
+            (op::DUP, _) => {
+                let top = stack.last().cloned().ok_or(anyhow!("Pickle stack underflow"))?;
+                stack.push(top);
+            }
+            (op::GLOBAL, Arg::Global { module, name }) => {
+                import(&mut report, &module, &name);
+                stack.push(StackItem::Other);
+            }
+            (op::INST, Arg::Global { module, name }) => {
+                import(&mut report, &module, &name);
+                pop_mark(&mut stack)?;
+                stack.push(StackItem::Other);
+            }
+            (op::STACK_GLOBAL, _) => {
+                match pop(&mut stack, 2)?.as_slice() {
+                    [StackItem::Str(module), StackItem::Str(name)] => {
+                        import(&mut report, module, name)
+                    }
+                    _ => {
+                        report
+                            .flagged
+                            .insert("STACK_GLOBAL with a computed module or name".to_string());
+                    }
+                }
+                stack.push(StackItem::Other);
+            }
+            (op::EXT1 | op::EXT2 | op::EXT4, Arg::Int(code)) => {
+                report
+                    .flagged
+                    .insert(format!("extension registry code {code}"));
+                stack.push(StackItem::Other);
+            }
+            (op::UNICODE | op::SHORT_BINUNICODE | op::BINUNICODE | op::BINUNICODE8, Arg::Str(s)) => {
+                stack.push(StackItem::Str(s))
+            }
+            (op::SHORT_BINSTRING | op::BINSTRING, Arg::Bytes(b)) => {
+                stack.push(StackItem::Str(String::from_utf8_lossy(&b).to_string()))
+            }
+            (op::STRING, Arg::Line(quoted)) => {
+                let unquoted = quoted.trim_matches(|c| c == '\'' || c == '"').to_string();
+                stack.push(StackItem::Str(unquoted))
+            }
+            (op::MEMOIZE, _) => {
+                let top = stack.last().cloned().ok_or(anyhow!("Pickle stack underflow"))?;
+                memo.insert(memo.len() as i64, top);
+            }
+            (op::BINPUT | op::LONG_BINPUT, Arg::Int(index)) => {
+                let top = stack.last().cloned().ok_or(anyhow!("Pickle stack underflow"))?;
+                memo.insert(index, top);
+            }
+            (op::PUT, Arg::Line(index)) => {
+                let top = stack.last().cloned().ok_or(anyhow!("Pickle stack underflow"))?;
+                memo.insert(index.trim().parse()?, top);
+            }
+            (op::BINGET | op::LONG_BINGET, Arg::Int(index)) => {
+                stack.push(memo.get(&index).cloned().unwrap_or(StackItem::Other))
+            }
+            (op::GET, Arg::Line(index)) => {
+                let index: i64 = index.trim().parse()?;
+                stack.push(memo.get(&index).cloned().unwrap_or(StackItem::Other))
+            }
+            (op::PROTO | op::FRAME, _) => {}
+            (op::TUPLE | op::LIST | op::DICT | op::FROZENSET | op::OBJ, _) => {
+                pop_mark(&mut stack)?;
+                stack.push(StackItem::Other);
+            }
+            (op::APPENDS | op::SETITEMS | op::ADDITEMS, _) => pop_mark(&mut stack)?,
+            (op::APPEND | op::BUILD, _) => {
+                pop(&mut stack, 1)?;
+            }
+            (op::SETITEM, _) => {
+                pop(&mut stack, 2)?;
+            }
+            (op::TUPLE1 | op::BINPERSID | op::READONLY_BUFFER, _) => {
+                pop(&mut stack, 1)?;
+                stack.push(StackItem::Other);
+            }
+            (op::TUPLE2 | op::REDUCE | op::NEWOBJ, _) => {
+                pop(&mut stack, 2)?;
+                stack.push(StackItem::Other);
+            }
+            (op::TUPLE3 | op::NEWOBJ_EX, _) => {
+                pop(&mut stack, 3)?;
+                stack.push(StackItem::Other);
+            }
+            // Everything left pushes a single plain value.
+            _ => stack.push(StackItem::Other),
+        }
+    }
+    Err(anyhow!("Pickle ends without a STOP"))
+}
+
+/// Torch's legacy (pre zip) format is a magic number, protocol version, system info, the object
+/// itself and the storage keys, pickled one after the other and followed by raw storage data.
+const LEGACY_PICKLE_COUNT: usize = 5;
+/// The legacy pickles sit at the start of the file, so there is no need to read the tensor data.
+/// A legacy checkpoint whose pickles don't end within this many bytes can't be analyzed, and is
+/// quarantined like any other checkpoint that can't be.
+pub const LEGACY_READ_LIMIT: u64 = 64 * 1024 * 1024;
+
+/// How many values [`load`] copies into and out of the memo before giving up. Torch checkpoints
+/// only memoize small tuples and empty containers, so even the largest stay far below this.
+pub const MEMO_COPY_LIMIT: usize = 1 << 22;
+
+/// Whether the file at `path` looks like a torch checkpoint: a zip archive with a pickle in it, or
+/// a legacy checkpoint, which starts with the pickle `PROTO` opcode.
+pub fn looks_like_checkpoint(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    let Ok(mut file) = File::open(path) else {
+        return false;
+    };
+    if file.read_exact(&mut magic).is_err() {
+        return false;
+    }
+    match magic {
+        [b'P', b'K', 3, 4] => zip::ZipArchive::new(file)
+            .is_ok_and(|archive| archive.file_names().any(|name| name.ends_with(".pkl"))),
+        [op::PROTO, protocol, ..] => protocol <= 5,
+        _ => false,
+    }
+}
+
+/// Analyzes every pickle in a torch checkpoint at `path`, either a zip archive with a `data.pkl`
+/// or the legacy format. Only the first [`LEGACY_READ_LIMIT`] bytes of a legacy checkpoint are
+/// read.
+#[tracing::instrument(level = "debug", skip(extra_allowed))]
+pub fn analyze_file(path: &Path, extra_allowed: &[String]) -> anyhow::Result<PickleReport> {
+    let allowed = SAFE_GLOBALS
+        .iter()
+        .copied()
+        .chain(extra_allowed.iter().map(String::as_str))
+        .collect::<Vec<_>>();
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open '{}'", path.to_string_lossy()))?;
+    let mut magic = [0u8; 4];
+    file.read_exact(&mut magic)
+        .with_context(|| format!("'{}' is too short to be a checkpoint", path.to_string_lossy()))?;
+
+    let mut report = PickleReport::default();
+    if magic == *b"PK\x03\x04" {
+        let mut archive = zip::ZipArchive::new(File::open(path)?)
+            .with_context(|| format!("Failed to open '{}' as a zip", path.to_string_lossy()))?;
+        let mut found = false;
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index)?;
+            if !entry.name().ends_with(".pkl") {
+                continue;
+            }
+            found = true;
+            debug!(entry = entry.name(), "Analyzing pickle");
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            let (entry_report, _) = analyze(&data, &allowed)
+                .with_context(|| format!("Failed to analyze '{}'", entry.name()))?;
+            report.merge(entry_report);
+        }
+        if !found {
+            return Err(anyhow!("'{}' has no data.pkl", path.to_string_lossy()));
+        }
+    } else {
+        let mut data = Vec::new();
+        File::open(path)?
+            .take(LEGACY_READ_LIMIT)
+            .read_to_end(&mut data)?;
+        let mut offset = 0;
+        let truncated = data.len() as u64 == LEGACY_READ_LIMIT;
+        for _ in 0..LEGACY_PICKLE_COUNT {
+            let (pickle_report, length) = analyze(&data[offset..], &allowed).with_context(|| {
+                if truncated {
+                    format!(
+                        "Failed to analyze '{}' within its first {} MiB",
+                        path.to_string_lossy(),
+                        LEGACY_READ_LIMIT >> 20
+                    )
+                } else {
+                    format!("Failed to analyze '{}'", path.to_string_lossy())
+                }
+            })?;
+            report.merge(pickle_report);
+            offset += length;
+        }
+    }
+    debug!(imports =? &report.imports, flagged =? &report.flagged, "Analyzed pickles");
+    Ok(report)
+}
+
+/// A value rebuilt by [`load`].
+///
+/// Calls are never made: a `REDUCE` or `NEWOBJ` on an allowed global is kept as a
+/// [`Value::Call`] for the caller to interpret.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    None,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+    Tuple(Vec<Value>),
+    List(Vec<Value>),
+    Dict(Vec<(Value, Value)>),
+    Global(String),
+    Call { global: String, args: Vec<Value> },
+    PersistentId(Box<Value>),
+}
+
+impl Value {
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            Value::Bool(b) => Some(*b as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The items of a tuple or list.
+    pub fn as_items(&self) -> Option<&[Value]> {
+        match self {
+            Value::Tuple(items) | Value::List(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Counts this value and everything in it, stopping once the count passes `limit`.
+    fn count(&self, limit: usize) -> usize {
+        let mut count = 0;
+        let mut pending = vec![self];
+        while let Some(value) = pending.pop() {
+            count += 1;
+            if count > limit {
+                break;
+            }
+            match value {
+                Value::Tuple(items) | Value::List(items) => pending.extend(items),
+                Value::Dict(entries) => pending.extend(entries.iter().flat_map(|(k, v)| [k, v])),
+                Value::Call { args, .. } => pending.extend(args),
+                Value::PersistentId(id) => pending.push(id),
+                _ => {}
+            }
+        }
+        count
+    }
+
+    /// Looks up a string key in a dict.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Dict(entries) => entries
+                .iter()
+                .find(|(k, _)| k.as_str() == Some(key))
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+/// A stack slot of [`load`]: either a value or a mark.
+#[derive(Debug, Clone, PartialEq)]
+enum Slot {
+    Mark,
+    Value(Value),
+}
+
+/// Rebuilds the object in a single pickle without running any code.
+///
+/// Only the globals in `allowed` may be imported; anything else, along with the opcodes that
+/// can only be interpreted by running code (`INST`, `OBJ` and the extension registry), is an
+/// error. The memo holds copies rather than references, which is enough for the tree-shaped
+/// objects torch saves. At most [`MEMO_COPY_LIMIT`] values are copied, since a pickle that gets
+/// the same entry over and over could otherwise double its size with every few bytes.
+pub fn load(data: &[u8], allowed: &[&str]) -> anyhow::Result<Value> {
+    load_with_limit(data, allowed, MEMO_COPY_LIMIT)
+}
+
+fn load_with_limit(data: &[u8], allowed: &[&str], copy_limit: usize) -> anyhow::Result<Value> {
+    let mut stack: Vec<Slot> = Vec::new();
+    let mut memo: HashMap<i64, Value> = HashMap::new();
+    let mut copied: usize = 0;
+    let mut reader = OpReader::new(data);
+
+    fn pop(stack: &mut Vec<Slot>) -> anyhow::Result<Value> {
+        match stack.pop() {
+            Some(Slot::Value(v)) => Ok(v),
+            Some(Slot::Mark) => Err(anyhow!("Pickle pops a mark as a value")),
+            None => Err(anyhow!("Pickle stack underflow")),
+        }
+    }
+    fn pop_mark(stack: &mut Vec<Slot>) -> anyhow::Result<Vec<Value>> {
+        let at = stack
+            .iter()
+            .rposition(|s| *s == Slot::Mark)
+            .ok_or(anyhow!("Pickle has no mark to pop to"))?;
+        let items = stack
+            .split_off(at + 1)
+            .into_iter()
+            .map(|s| match s {
+                Slot::Value(v) => v,
+                Slot::Mark => unreachable!("the last mark was found above"),
+            })
+            .collect();
+        stack.truncate(at);
+        Ok(items)
+    }
+    fn top(stack: &mut [Slot]) -> anyhow::Result<&mut Value> {
+        match stack.last_mut() {
+            Some(Slot::Value(v)) => Ok(v),
+            _ => Err(anyhow!("Pickle expects a value on top of the stack")),
+        }
+    }
+    let mut copy = |value: &Value| -> anyhow::Result<Value> {
+        copied += value.count(copy_limit - copied);
+        if copied > copy_limit {
+            return Err(anyhow!("Pickle copies more than {copy_limit} values through its memo"));
+        }
+        Ok(value.clone())
+    };
+    fn set_items(target: &mut Value, items: Vec<Value>) -> anyhow::Result<()> {
+        let Value::Dict(entries) = target else {
+            return Err(anyhow!("Pickle sets items on something that isn't a dict"));
+        };
+        let mut items = items.into_iter();
+        while let (Some(key), Some(value)) = (items.next(), items.next()) {
+            entries.push((key, value));
+        }
+        Ok(())
+    }
+    let call = |global: Value, args: Value| -> anyhow::Result<Value> {
+        let Value::Global(global) = global else {
+            return Err(anyhow!("Pickle calls something that isn't an imported global"));
+        };
+        let args = match args {
+            Value::Tuple(args) => args,
+            other => vec![other],
+        };
+        // An ordered dict is only ever created empty and then filled with SETITEMS.
+        if global == "collections.OrderedDict" && args.is_empty() {
+            return Ok(Value::Dict(Vec::new()));
+        }
+        Ok(Value::Call { global, args })
+    };
+
+    while let Some(instruction) = reader.read()? {
+        let value = match (instruction.opcode, instruction.arg) {
+            (op::STOP, _) => return pop(&mut stack),
+            (op::PROTO | op::FRAME, _) => continue,
+            (op::MARK, _) => {
+                stack.push(Slot::Mark);
+                continue;
+            }
+            (op::POP, _) => {
+                stack.pop().ok_or(anyhow!("Pickle stack underflow"))?;
+                continue;
+            }
+            (op::POP_MARK, _) => {
+                pop_mark(&mut stack)?;
+                continue;
+            }
+            (op::DUP, _) => top(&mut stack)?.clone(),
+            (op::NONE, _) => Value::None,
+            (op::NEWTRUE, _) => Value::Bool(true),
+            (op::NEWFALSE, _) => Value::Bool(false),
+            (op::BININT | op::BININT1 | op::BININT2, Arg::Int(i)) => Value::Int(i),
+            (op::INT, Arg::Line(line)) => match line.trim() {
+                "00" => Value::Bool(false),
+                "01" => Value::Bool(true),
+                i => Value::Int(i.parse()?),
+            },
+            (op::LONG, Arg::Line(line)) => Value::Int(line.trim().trim_end_matches('L').parse()?),
+            (op::LONG1 | op::LONG4, Arg::Long(bytes)) => {
+                if bytes.len() > 8 {
+                    return Err(anyhow!("Pickle has an integer wider than 64 bits"));
+                }
+                let fill = if bytes.last().is_some_and(|b| b & 0x80 != 0) { 0xff } else { 0 };
+                let mut le = [fill; 8];
+                le[..bytes.len()].copy_from_slice(&bytes);
+                Value::Int(i64::from_le_bytes(le))
+            }
+            (op::FLOAT, Arg::Line(line)) => Value::Float(line.trim().parse()?),
+            (op::BINFLOAT, Arg::Float(f)) => Value::Float(f),
+            (_, Arg::Str(s)) => Value::Str(s),
+            (op::STRING, Arg::Line(quoted)) => {
+                Value::Str(quoted.trim_matches(|c| c == '\'' || c == '"').to_string())
+            }
+            (op::SHORT_BINSTRING | op::BINSTRING, Arg::Bytes(b)) => {
+                Value::Str(String::from_utf8_lossy(&b).to_string())
+            }
+            (_, Arg::Bytes(b)) => Value::Bytes(b),
+            (op::EMPTY_TUPLE, _) => Value::Tuple(Vec::new()),
+            (op::TUPLE1 | op::TUPLE2 | op::TUPLE3, _) => {
+                let count = (instruction.opcode - op::TUPLE1 + 1) as usize;
+                let at = stack
+                    .len()
+                    .checked_sub(count)
+                    .ok_or(anyhow!("Pickle stack underflow"))?;
+                let mut items = Vec::with_capacity(count);
+                for slot in stack.split_off(at) {
+                    match slot {
+                        Slot::Value(v) => items.push(v),
+                        Slot::Mark => return Err(anyhow!("Pickle puts a mark in a tuple")),
+                    }
+                }
+                Value::Tuple(items)
+            }
+            (op::TUPLE, _) => Value::Tuple(pop_mark(&mut stack)?),
+            (op::EMPTY_LIST, _) => Value::List(Vec::new()),
+            (op::LIST, _) => Value::List(pop_mark(&mut stack)?),
+            (op::APPEND, _) => {
+                let item = pop(&mut stack)?;
+                match top(&mut stack)? {
+                    Value::List(items) => items.push(item),
+                    _ => return Err(anyhow!("Pickle appends to something that isn't a list")),
+                }
+                continue;
+            }
+            (op::APPENDS, _) => {
+                let new_items = pop_mark(&mut stack)?;
+                match top(&mut stack)? {
+                    Value::List(items) => items.extend(new_items),
+                    _ => return Err(anyhow!("Pickle appends to something that isn't a list")),
+                }
+                continue;
+            }
+            (op::EMPTY_DICT, _) => Value::Dict(Vec::new()),
+            (op::DICT, _) => {
+                let mut dict = Value::Dict(Vec::new());
+                set_items(&mut dict, pop_mark(&mut stack)?)?;
+                dict
+            }
+            (op::SETITEM, _) => {
+                let value = pop(&mut stack)?;
+                let key = pop(&mut stack)?;
+                set_items(top(&mut stack)?, vec![key, value])?;
+                continue;
+            }
+            (op::SETITEMS, _) => {
+                let items = pop_mark(&mut stack)?;
+                set_items(top(&mut stack)?, items)?;
+                continue;
+            }
+            (op::GLOBAL, Arg::Global { module, name }) => {
+                let global = format!("{module}.{name}");
+                if !allowed.contains(&global.as_str()) {
+                    return Err(anyhow!("Refusing to import {global}"));
+                }
+                Value::Global(global)
+            }
+            (op::STACK_GLOBAL, _) => {
+                let name = pop(&mut stack)?;
+                let module = pop(&mut stack)?;
+                let (Some(module), Some(name)) = (module.as_str(), name.as_str()) else {
+                    return Err(anyhow!("Refusing a STACK_GLOBAL with a computed module or name"));
+                };
+                let global = format!("{module}.{name}");
+                if !allowed.contains(&global.as_str()) {
+                    return Err(anyhow!("Refusing to import {global}"));
+                }
+                Value::Global(global)
+            }
+            (op::REDUCE | op::NEWOBJ, _) => {
+                let args = pop(&mut stack)?;
+                let global = pop(&mut stack)?;
+                call(global, args)?
+            }
+            (op::BUILD, _) => {
+                // Object state, such as a state dict's `_metadata`, isn't needed.
+                pop(&mut stack)?;
+                continue;
+            }
+            (op::BINPERSID, _) => Value::PersistentId(Box::new(pop(&mut stack)?)),
+            (op::PERSID, Arg::Line(id)) => Value::PersistentId(Box::new(Value::Str(id))),
+            (op::MEMOIZE, _) => {
+                let value = copy(top(&mut stack)?)?;
+                memo.insert(memo.len() as i64, value);
+                continue;
+            }
+            (op::BINPUT | op::LONG_BINPUT, Arg::Int(index)) => {
+                let value = copy(top(&mut stack)?)?;
+                memo.insert(index, value);
+                continue;
+            }
+            (op::PUT, Arg::Line(index)) => {
+                let value = copy(top(&mut stack)?)?;
+                memo.insert(index.trim().parse()?, value);
+                continue;
+            }
+            (op::BINGET | op::LONG_BINGET, Arg::Int(index)) => copy(
+                memo.get(&index)
+                    .ok_or(anyhow!("Pickle refers to missing memo entry {index}"))?,
+            )?,
+            (op::GET, Arg::Line(index)) => {
+                let index: i64 = index.trim().parse()?;
+                copy(
+                    memo.get(&index)
+                        .ok_or(anyhow!("Pickle refers to missing memo entry {index}"))?,
+                )?
+            }
+            (opcode, _) => {
+                return Err(anyhow!(
+                    "Refusing pickle opcode 0x{opcode:02x} at byte {}",
+                    reader.position()
+                ))
+            }
+        };
+        stack.push(Slot::Value(value));
+    }
+    Err(anyhow!("Pickle ends without a STOP"))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Write;
+
+    use tempfile::NamedTempFile;
+    use zip::write::SimpleFileOptions;
+
+    use super::*;
+
+    fn short_str(s: &str) -> Vec<u8> {
+        [&[op::SHORT_BINUNICODE, s.len() as u8], s.as_bytes()].concat()
+    }
+
+    fn global(opcode: u8, module: &str, name: &str) -> Vec<u8> {
+        [&[opcode], format!("{module}\n{name}\n").as_bytes()].concat()
+    }
+
+    fn analyze_all(data: &[u8]) -> anyhow::Result<PickleReport> {
+        analyze(data, SAFE_GLOBALS).map(|(report, _)| report)
+    }
+
+    fn imports(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn resolves_global() {
+        let data = [
+            &[op::PROTO, 2][..],
+            &global(op::GLOBAL, "collections", "OrderedDict"),
+            &[op::EMPTY_TUPLE, op::REDUCE, op::STOP],
+        ]
+        .concat();
+        let report = analyze_all(&data).unwrap();
+        assert_eq!(report.imports, imports(&["collections.OrderedDict"]));
+        assert!(report.is_safe());
+    }
+
+    #[test]
+    fn flags_global_outside_allowlist() {
+        let data = [
+            &global(op::GLOBAL, "posix", "system")[..],
+            &short_str("ls"),
+            &[op::TUPLE1, op::REDUCE, op::STOP],
+        ]
+        .concat();
+        let report = analyze_all(&data).unwrap();
+        assert_eq!(report.flagged, imports(&["posix.system"]));
+        let report = analyze(&data, &["posix.system"]).unwrap().0;
+        assert!(report.is_safe());
+    }
+
+    #[test]
+    fn resolves_stack_global() {
+        let data = [
+            &[op::PROTO, 4][..],
+            &short_str("torch._utils"),
+            &short_str("_rebuild_tensor_v2"),
+            &[op::STACK_GLOBAL, op::STOP],
+        ]
+        .concat();
+        let report = analyze_all(&data).unwrap();
+        assert_eq!(report.imports, imports(&["torch._utils._rebuild_tensor_v2"]));
+        assert!(report.is_safe());
+    }
+
+    #[test]
+    fn resolves_stack_global_from_memo() {
+        let data = [
+            &[op::PROTO, 4][..],
+            &short_str("builtins"),
+            &[op::MEMOIZE, op::POP],
+            &short_str("eval"),
+            &[op::BINPUT, 7, op::POP],
+            &[op::BINGET, 0, op::BINGET, 7, op::STACK_GLOBAL, op::STOP],
+        ]
+        .concat();
+        let report = analyze_all(&data).unwrap();
+        assert_eq!(report.flagged, imports(&["builtins.eval"]));
+    }
+
+    #[test]
+    fn flags_inst() {
+        let data = [
+            &[op::MARK][..],
+            b"S'ls'\n",
+            &global(op::INST, "os", "system"),
+            &[op::STOP],
+        ]
+        .concat();
+        let report = analyze_all(&data).unwrap();
+        assert_eq!(report.flagged, imports(&["os.system"]));
+    }
+
+    #[test]
+    fn flags_computed_stack_global() {
+        let data = [
+            &short_str("os")[..],
+            &global(op::GLOBAL, "collections", "OrderedDict"),
+            &[op::EMPTY_TUPLE, op::REDUCE, op::STACK_GLOBAL, op::STOP],
+        ]
+        .concat();
+        let report = analyze_all(&data).unwrap();
+        assert_eq!(
+            report.flagged,
+            imports(&["STACK_GLOBAL with a computed module or name"])
+        );
+    }
+
+    #[test]
+    fn flags_extension_registry() {
+        let cases: [(&[u8], &str); 3] = [
+            (&[op::EXT1, 5, op::STOP], "extension registry code 5"),
+            (&[op::EXT2, 1, 2, op::STOP], "extension registry code 513"),
+            (&[op::EXT4, 1, 0, 0, 1, op::STOP], "extension registry code 16777217"),
+        ];
+        for (data, flagged) in cases {
+            assert_eq!(analyze_all(data).unwrap().flagged, imports(&[flagged]));
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_pickle() {
+        for data in [&[op::PROTO][..], b"cos\nsys", &[op::BINUNICODE, 9, 0, 0, 0, b'a'], b"N"] {
+            assert!(analyze_all(data).is_err(), "{data:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_opcode() {
+        let error = analyze_all(&[op::PROTO, 2, 0xff, op::STOP]).unwrap_err();
+        assert!(error.to_string().contains("Unknown pickle opcode 0xff at byte 2"));
+    }
+
+    #[test]
+    fn returns_pickle_length() {
+        let (_, length) = analyze(&[op::NONE, op::STOP, op::NONE, op::STOP], SAFE_GLOBALS).unwrap();
+        assert_eq!(length, 2);
+    }
+
+    fn bin_str(s: &str) -> Vec<u8> {
+        [&[op::BINUNICODE][..], &(s.len() as u32).to_le_bytes(), s.as_bytes()].concat()
+    }
+
+    /// `OrderedDict(weight=<2x3 transposed view of a FloatStorage>, global_step=5)`, pickled
+    /// the way `torch.save` does.
+    pub(crate) fn get_state_dict_pickle() -> Vec<u8> {
+        [
+            &[op::PROTO, 2][..],
+            &global(op::GLOBAL, "collections", "OrderedDict"),
+            &[op::EMPTY_TUPLE, op::REDUCE, op::BINPUT, 0, op::MARK],
+            &bin_str("weight"),
+            &global(op::GLOBAL, "torch._utils", "_rebuild_tensor_v2"),
+            &[op::MARK, op::MARK],
+            &bin_str("storage"),
+            &global(op::GLOBAL, "torch", "FloatStorage"),
+            &bin_str("0"),
+            &bin_str("cpu"),
+            &[op::BININT1, 6, op::TUPLE, op::BINPERSID],
+            &[op::BININT1, 0],
+            &[op::BININT1, 2, op::BININT1, 3, op::TUPLE2],
+            &[op::BININT1, 1, op::BININT1, 2, op::TUPLE2],
+            &[op::NEWFALSE],
+            &global(op::GLOBAL, "collections", "OrderedDict"),
+            &[op::EMPTY_TUPLE, op::REDUCE, op::TUPLE, op::REDUCE],
+            &bin_str("global_step"),
+            &[op::LONG1, 1, 0xfb],
+            &[op::SETITEMS, op::EMPTY_DICT, op::BUILD, op::STOP],
+        ]
+        .concat()
+    }
+
+    fn ints(values: &[i64]) -> Value {
+        Value::Tuple(values.iter().map(|i| Value::Int(*i)).collect())
+    }
+
+    #[test]
+    fn loads_state_dict() {
+        let root = load(&get_state_dict_pickle(), SAFE_GLOBALS).unwrap();
+        let storage = Value::PersistentId(Box::new(Value::Tuple(vec![
+            Value::Str("storage".to_string()),
+            Value::Global("torch.FloatStorage".to_string()),
+            Value::Str("0".to_string()),
+            Value::Str("cpu".to_string()),
+            Value::Int(6),
+        ])));
+        let weight = Value::Call {
+            global: "torch._utils._rebuild_tensor_v2".to_string(),
+            args: vec![
+                storage,
+                Value::Int(0),
+                ints(&[2, 3]),
+                ints(&[1, 2]),
+                Value::Bool(false),
+                Value::Dict(Vec::new()),
+            ],
+        };
+        assert_eq!(root.get("weight"), Some(&weight));
+        assert_eq!(root.get("global_step"), Some(&Value::Int(-5)));
+    }
+
+    #[test]
+    fn loads_memoized_values() {
+        let data = [
+            &[op::PROTO, 4][..],
+            &short_str("a"),
+            &[op::MEMOIZE, op::BINGET, 0, op::BINGET, 0, op::TUPLE3, op::STOP],
+        ]
+        .concat();
+        let a = Value::Str("a".to_string());
+        assert_eq!(
+            load(&data, SAFE_GLOBALS).unwrap(),
+            Value::Tuple(vec![a.clone(), a.clone(), a])
+        );
+        assert!(load(&[op::BINGET, 3, op::STOP], SAFE_GLOBALS).is_err());
+    }
+
+    #[test]
+    fn load_limits_memo_copies() {
+        // Every level is a tuple of two copies of the one before.
+        let get_data = |levels: u8| {
+            let mut data = [&[op::PROTO, 4][..], &short_str("a"), &[op::MEMOIZE]].concat();
+            for level in 0..levels {
+                data.extend([op::BINGET, level, op::BINGET, level, op::TUPLE2, op::MEMOIZE]);
+            }
+            data.push(op::STOP);
+            data
+        };
+        let root = load_with_limit(&get_data(5), SAFE_GLOBALS, 1000).unwrap();
+        assert_eq!(root.count(usize::MAX), 63);
+
+        let error = load_with_limit(&get_data(40), SAFE_GLOBALS, 1000).unwrap_err();
+        assert!(error.to_string().contains("copies more than 1000 values"));
+    }
+
+    #[test]
+    fn load_refuses_global_outside_allowlist() {
+        let data = [
+            &global(op::GLOBAL, "posix", "system")[..],
+            &short_str("ls"),
+            &[op::TUPLE1, op::REDUCE, op::STOP],
+        ]
+        .concat();
+        let error = load(&data, SAFE_GLOBALS).unwrap_err();
+        assert_eq!(error.to_string(), "Refusing to import posix.system");
+
+        let data = [
+            &short_str("builtins")[..],
+            &short_str("eval"),
+            &[op::STACK_GLOBAL, op::STOP],
+        ]
+        .concat();
+        let error = load(&data, SAFE_GLOBALS).unwrap_err();
+        assert_eq!(error.to_string(), "Refusing to import builtins.eval");
+    }
+
+    #[test]
+    fn load_refuses_code_execution_opcodes() {
+        let computed = [
+            &short_str("os")[..],
+            &[op::EMPTY_TUPLE, op::STACK_GLOBAL, op::STOP],
+        ]
+        .concat();
+        let inst = [
+            &[op::MARK][..],
+            &global(op::INST, "collections", "OrderedDict"),
+            &[op::STOP],
+        ]
+        .concat();
+        for data in [&computed[..], &inst, &[op::EXT1, 1, op::STOP]] {
+            assert!(load(data, SAFE_GLOBALS).unwrap_err().to_string().starts_with("Refusing"));
+        }
+    }
+
+    #[test]
+    fn analyzes_zip_checkpoint() {
+        let data = [
+            &[op::PROTO, 2][..],
+            &global(op::GLOBAL, "collections", "OrderedDict"),
+            &[op::EMPTY_TUPLE, op::REDUCE, op::POP],
+            &global(op::GLOBAL, "posix", "system"),
+            &[op::STOP],
+        ]
+        .concat();
+        let file = NamedTempFile::new().unwrap();
+        let mut archive = zip::ZipWriter::new(file.reopen().unwrap());
+        archive
+            .start_file("archive/data.pkl", SimpleFileOptions::default())
+            .unwrap();
+        archive.write_all(&data).unwrap();
+        archive
+            .start_file("archive/data/0", SimpleFileOptions::default())
+            .unwrap();
+        archive.write_all(&[0; 16]).unwrap();
+        archive.finish().unwrap();
+
+        let report = analyze_file(file.path(), &[]).unwrap();
+        assert_eq!(
+            report.imports,
+            imports(&["collections.OrderedDict", "posix.system"])
+        );
+        assert_eq!(report.flagged, imports(&["posix.system"]));
+        assert!(analyze_file(file.path(), &["posix.system".to_string()])
+            .unwrap()
+            .is_safe());
+    }
+
+    #[test]
+    fn analyzes_legacy_checkpoint() {
+        let mut file = NamedTempFile::new().unwrap();
+        for _ in 0..2 {
+            file.write_all(&[op::NONE, op::STOP]).unwrap();
+        }
+        file.write_all(&global(op::GLOBAL, "posix", "system")).unwrap();
+        file.write_all(&[op::STOP]).unwrap();
+        for _ in 0..2 {
+            file.write_all(&[op::NONE, op::STOP]).unwrap();
+        }
+        file.write_all(&[0xff; 32]).unwrap();
+
+        let report = analyze_file(file.path(), &[]).unwrap();
+        assert_eq!(report.flagged, imports(&["posix.system"]));
+    }
+
+    #[test]
+    fn recognizes_checkpoints_by_content() {
+        let legacy = NamedTempFile::new().unwrap();
+        std::fs::write(legacy.path(), [op::PROTO, 2, op::NONE, op::STOP]).unwrap();
+        assert!(looks_like_checkpoint(legacy.path()));
+
+        let torch_zip = NamedTempFile::new().unwrap();
+        let mut archive = zip::ZipWriter::new(torch_zip.reopen().unwrap());
+        archive
+            .start_file("archive/data.pkl", SimpleFileOptions::default())
+            .unwrap();
+        archive.write_all(&[op::NONE, op::STOP]).unwrap();
+        archive.finish().unwrap();
+        assert!(looks_like_checkpoint(torch_zip.path()));
+
+        let other_zip = NamedTempFile::new().unwrap();
+        let mut archive = zip::ZipWriter::new(other_zip.reopen().unwrap());
+        archive
+            .start_file("readme.txt", SimpleFileOptions::default())
+            .unwrap();
+        archive.finish().unwrap();
+        assert!(!looks_like_checkpoint(other_zip.path()));
+
+        let raw = NamedTempFile::new().unwrap();
+        std::fs::write(raw.path(), [0x12; 64]).unwrap();
+        assert!(!looks_like_checkpoint(raw.path()));
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use indicatif::HumanBytes;
+use serde::Serialize;
+
+use crate::preflight::PlannedDownload;
+use crate::report::write_table;
+
+/// What a download would do if it were run now.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PlannedAction {
+    Download,
+    Resume { bytes_present: u64 },
+    Overwrite,
+    SkipAlreadyPresent,
+    /// A file of the expected size is there, but it is only hashed when the download runs.
+    Verify,
+    Conflict { reason: String },
+    /// Refused because Civitai's scans didn't pass.
+    Blocked { reason: String },
+}
+
+impl PlannedAction {
+    fn describe(&self) -> String {
+        match self {
+            PlannedAction::Download => "download".to_string(),
+            PlannedAction::Resume { bytes_present } => {
+                format!("resume from {}", HumanBytes(*bytes_present))
+            }
+            PlannedAction::Overwrite => "overwrite".to_string(),
+            PlannedAction::SkipAlreadyPresent => "skip (present)".to_string(),
+            PlannedAction::Verify => "verify (present)".to_string(),
+            PlannedAction::Conflict { reason } => format!("CONFLICT: {reason}"),
+            PlannedAction::Blocked { reason } => format!("BLOCKED: {reason}"),
+        }
+    }
+}
+
+/// A fully resolved download that has not been started.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlannedItem {
+    pub model_id: i64,
+    pub model_name: String,
+    pub model_version_id: i64,
+    pub model_version_name: String,
+    pub file_id: i64,
+    pub file_name: String,
+    pub file_type: String,
+    pub format: Option<String>,
+    pub size_bytes: Option<u64>,
+    pub url: String,
+    pub target_path: PathBuf,
+    pub action: PlannedAction,
+}
+
+impl From<&PlannedItem> for PlannedDownload {
+    fn from(item: &PlannedItem) -> Self {
+        PlannedDownload {
+            directory: item
+                .target_path
+                .parent()
+                .map(|p| p.to_path_buf())
+                .unwrap_or_default(),
+            file_name: item.file_name.clone(),
+            size_bytes: item.size_bytes.unwrap_or_default(),
+        }
+    }
+}
+
+/// A human-readable table of [`PlannedItem`]s.
+pub struct Plan<'a>(pub &'a [PlannedItem]);
+
+impl fmt::Display for Plan<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = ["Action", "Model", "Version", "File", "Size", "Target"];
+        let rows = self
+            .0
+            .iter()
+            .map(|item| {
+                [
+                    item.action.describe(),
+                    format!("{} ({})", item.model_id, item.model_name),
+                    format!("{} ({})", item.model_version_id, item.model_version_name),
+                    item.file_name.clone(),
+                    item.size_bytes
+                        .map(|b| HumanBytes(b).to_string())
+                        .unwrap_or_else(|| "?".to_string()),
+                    item.target_path.to_string_lossy().to_string(),
+                ]
+            })
+            .collect::<Vec<_>>();
+        write_table(f, &header, &rows)?;
+
+        let to_fetch = self
+            .0
+            .iter()
+            .filter(|i| {
+                matches!(
+                    i.action,
+                    PlannedAction::Download | PlannedAction::Resume { .. } | PlannedAction::Overwrite
+                )
+            })
+            .collect::<Vec<_>>();
+        let bytes = to_fetch
+            .iter()
+            .map(|i| match i.action {
+                PlannedAction::Resume { bytes_present } => {
+                    i.size_bytes.unwrap_or_default().saturating_sub(bytes_present)
+                }
+                _ => i.size_bytes.unwrap_or_default(),
+            })
+            .sum::<u64>();
+        writeln!(
+            f,
+            "\n{} of {} files would be downloaded ({})",
+            to_fetch.len(),
+            self.0.len(),
+            HumanBytes(bytes)
+        )
+    }
+}
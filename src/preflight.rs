@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use indicatif::HumanBytes;
+use serde::Serialize;
+use tracing::debug;
+
+use crate::{get_part_path, get_segmented_path};
+
+/// A file that is about to be downloaded into `directory`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedDownload {
+    pub directory: PathBuf,
+    pub file_name: String,
+    pub size_bytes: u64,
+}
+
+/// How much space a batch needs on one filesystem, and how much is free.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FilesystemUsage {
+    pub directories: Vec<PathBuf>,
+    pub required_bytes: u64,
+    pub available_bytes: u64,
+}
+
+impl FilesystemUsage {
+    pub fn is_sufficient(&self) -> bool {
+        self.required_bytes <= self.available_bytes
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DiskSpaceReport {
+    pub filesystems: Vec<FilesystemUsage>,
+}
+
+impl DiskSpaceReport {
+    pub fn is_sufficient(&self) -> bool {
+        self.filesystems.iter().all(FilesystemUsage::is_sufficient)
+    }
+}
+
+impl fmt::Display for DiskSpaceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for usage in &self.filesystems {
+            let directories = usage
+                .directories
+                .iter()
+                .map(|d| d.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(
+                f,
+                "[{}] {} required, {} available: {}",
+                if usage.is_sufficient() { "ok" } else { "INSUFFICIENT" },
+                HumanBytes(usage.required_bytes),
+                HumanBytes(usage.available_bytes),
+                directories
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Sums the space still needed by `downloads` per filesystem and compares it with the free
+/// space on each.
+///
+/// Files that are already complete are not counted, and partial downloads only count the bytes
+/// that are still missing.
+pub fn check_disk_space(downloads: &[PlannedDownload]) -> anyhow::Result<DiskSpaceReport> {
+    let mut filesystems: BTreeMap<String, FilesystemUsage> = BTreeMap::new();
+    for download in downloads {
+        let existing_directory = get_existing_ancestor(&download.directory);
+        let key = get_filesystem_key(&existing_directory)?;
+        let required = get_remaining_bytes(download);
+        debug!(download =? download, filesystem = key, required, "Planned download");
+
+        let usage = match filesystems.get_mut(&key) {
+            Some(usage) => usage,
+            None => {
+                let available_bytes = fs2::available_space(&existing_directory).with_context(|| {
+                    format!(
+                        "Failed to get free space for '{}'",
+                        existing_directory.to_string_lossy()
+                    )
+                })?;
+                filesystems.entry(key).or_insert(FilesystemUsage {
+                    directories: Vec::new(),
+                    required_bytes: 0,
+                    available_bytes,
+                })
+            }
+        };
+        usage.required_bytes += required;
+        if !usage.directories.contains(&download.directory) {
+            usage.directories.push(download.directory.clone());
+        }
+    }
+    Ok(DiskSpaceReport {
+        filesystems: filesystems.into_values().collect(),
+    })
+}
+
+fn get_remaining_bytes(download: &PlannedDownload) -> u64 {
+    let final_path = download.directory.join(&download.file_name);
+    if final_path
+        .metadata()
+        .map(|m| m.len() == download.size_bytes)
+        .unwrap_or(false)
+    {
+        return 0;
+    }
+    // A segmented download preallocates its whole file, so it already holds that space.
+    let partial = [get_part_path(&final_path), get_segmented_path(&final_path)]
+        .iter()
+        .filter_map(|p| p.metadata().ok())
+        .map(|m| m.len())
+        .max()
+        .unwrap_or(0);
+    download.size_bytes.saturating_sub(partial)
+}
+
+/// Free space and the device can only be queried for a path that exists, and directories that
+/// are yet to be created will end up on the filesystem of their closest existing ancestor.
+fn get_existing_ancestor(directory: &Path) -> PathBuf {
+    directory
+        .ancestors()
+        .find(|a| a.exists())
+        .unwrap_or(directory)
+        .to_path_buf()
+}
+
+#[cfg(unix)]
+fn get_filesystem_key(directory: &Path) -> anyhow::Result<String> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(directory.metadata()?.dev().to_string())
+}
+
+#[cfg(not(unix))]
+fn get_filesystem_key(directory: &Path) -> anyhow::Result<String> {
+    Ok(directory
+        .ancestors()
+        .last()
+        .unwrap_or(directory)
+        .to_string_lossy()
+        .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_download(directory: &Path, file_name: &str, size_bytes: u64) -> PlannedDownload {
+        PlannedDownload {
+            directory: directory.to_path_buf(),
+            file_name: file_name.to_string(),
+            size_bytes,
+        }
+    }
+
+    #[test]
+    fn counts_only_missing_bytes() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path();
+        assert_eq!(get_remaining_bytes(&get_download(path, "new.safetensors", 100)), 100);
+
+        std::fs::write(path.join("done.safetensors"), [0; 100]).unwrap();
+        assert_eq!(get_remaining_bytes(&get_download(path, "done.safetensors", 100)), 0);
+        // A file of the wrong size is downloaded again.
+        assert_eq!(get_remaining_bytes(&get_download(path, "done.safetensors", 150)), 150);
+
+        std::fs::write(get_part_path(&path.join("partial.safetensors")), [0; 30]).unwrap();
+        assert_eq!(get_remaining_bytes(&get_download(path, "partial.safetensors", 100)), 70);
+
+        std::fs::write(get_segmented_path(&path.join("segmented.safetensors")), [0; 100]).unwrap();
+        assert_eq!(get_remaining_bytes(&get_download(path, "segmented.safetensors", 100)), 0);
+    }
+
+    #[test]
+    fn sums_downloads_per_filesystem() {
+        let directory = tempfile::tempdir().unwrap();
+        let models = directory.path().join("models");
+        let loras = directory.path().join("models").join("Lora");
+        std::fs::create_dir(&models).unwrap();
+        std::fs::write(get_part_path(&models.join("a.safetensors")), [0; 40]).unwrap();
+
+        let report = check_disk_space(&[
+            get_download(&models, "a.safetensors", 100),
+            get_download(&loras, "b.safetensors", 200),
+            get_download(&loras, "c.safetensors", 300),
+        ])
+        .unwrap();
+        assert_eq!(report.filesystems.len(), 1);
+        let usage = &report.filesystems[0];
+        assert_eq!(usage.required_bytes, 60 + 200 + 300);
+        assert_eq!(usage.directories, [models, loras]);
+    }
+
+    #[test]
+    fn finds_existing_ancestor() {
+        let directory = tempfile::tempdir().unwrap();
+        let missing = directory.path().join("a").join("b");
+        assert_eq!(get_existing_ancestor(&missing), directory.path());
+        assert_eq!(get_existing_ancestor(directory.path()), directory.path());
+    }
+}
@@ -0,0 +1,51 @@
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use image::ImageFormat;
+use tracing::debug;
+
+use crate::model::model_version::{Image, NSFW};
+
+/// Picks the first `limit` images rated at most `max_nsfw`.
+///
+/// Images without a rating are treated as the most explicit level.
+pub fn select_images<'a>(images: &'a [Image], limit: usize, max_nsfw: &NSFW) -> Vec<&'a Image> {
+    images
+        .iter()
+        .filter(|i| i.nsfw.as_ref().unwrap_or(&NSFW::X) <= max_nsfw)
+        .take(limit)
+        .collect()
+}
+
+/// The path of the `index`th preview of `model_path`.
+///
+/// The first preview is `<stem>.preview.png`, which is what the web UIs look for. Any further
+/// ones are numbered, `<stem>.preview.1.png` and so on.
+pub fn get_preview_path(model_path: &Path, index: usize) -> PathBuf {
+    let stem = model_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let file_name = match index {
+        0 => format!("{stem}.preview.png"),
+        n => format!("{stem}.preview.{n}.png"),
+    };
+    model_path.with_file_name(file_name)
+}
+
+/// Re-encodes a JPEG or WebP image as PNG. PNGs are returned unchanged.
+pub fn convert_to_png(bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let format = image::guess_format(bytes).context("Unrecognized preview image format")?;
+    if format == ImageFormat::Png {
+        return Ok(bytes.to_vec());
+    }
+    debug!(format =? format, "Converting preview to PNG");
+    let decoded = image::load_from_memory_with_format(bytes, format)
+        .with_context(|| format!("Failed to decode {format:?} preview"))?;
+    let mut png = Vec::new();
+    decoded
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .context("Failed to encode preview as PNG")?;
+    Ok(png)
+}
@@ -0,0 +1,250 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use indicatif::HumanBytes;
+
+use crate::hashing::{HashKind, HashMismatch};
+use crate::model::model_version::ModelVersion;
+use crate::model::Model;
+
+/// What happened to a single requested download.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DownloadOutcome {
+    Downloaded { path: PathBuf, bytes: u64 },
+    SkippedAlreadyPresent { path: PathBuf },
+    SkippedByPolicy { reason: String },
+    /// The file on disk didn't match the hash Civitai reported for it.
+    HashMismatch {
+        path: PathBuf,
+        kind: HashKind,
+        expected: String,
+        actual: String,
+    },
+    Failed { reason: String },
+}
+
+impl DownloadOutcome {
+    /// The outcome for `error`, which is a [`DownloadOutcome::HashMismatch`] when the error is a
+    /// [`HashMismatch`].
+    pub fn failed(error: &anyhow::Error) -> Self {
+        match error.downcast_ref::<HashMismatch>() {
+            Some(mismatch) => DownloadOutcome::HashMismatch {
+                path: mismatch.path.clone(),
+                kind: mismatch.kind,
+                expected: mismatch.expected.clone(),
+                actual: mismatch.actual.clone(),
+            },
+            None => DownloadOutcome::Failed {
+                reason: format!("{error:#}"),
+            },
+        }
+    }
+
+    pub fn is_failure(&self) -> bool {
+        matches!(
+            self,
+            DownloadOutcome::Failed { .. } | DownloadOutcome::HashMismatch { .. }
+        )
+    }
+
+    /// Where the file is, if it was downloaded or already there.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            DownloadOutcome::Downloaded { path, .. }
+            | DownloadOutcome::SkippedAlreadyPresent { path } => Some(path),
+            _ => None,
+        }
+    }
+
+    fn status(&self) -> &'static str {
+        match self {
+            DownloadOutcome::Downloaded { .. } => "downloaded",
+            DownloadOutcome::SkippedAlreadyPresent { .. } => "present",
+            DownloadOutcome::SkippedByPolicy { .. } => "skipped",
+            DownloadOutcome::HashMismatch { .. } => "MISMATCH",
+            DownloadOutcome::Failed { .. } => "FAILED",
+        }
+    }
+
+    fn details(&self) -> String {
+        match self {
+            DownloadOutcome::Downloaded { path, bytes } => {
+                format!("{} ({})", path.to_string_lossy(), HumanBytes(*bytes))
+            }
+            DownloadOutcome::SkippedAlreadyPresent { path } => path.to_string_lossy().to_string(),
+            DownloadOutcome::HashMismatch {
+                path,
+                kind,
+                expected,
+                actual,
+            } => format!(
+                "{}: expected {} {}, got {}",
+                path.to_string_lossy(),
+                kind.as_ref(),
+                expected,
+                actual
+            ),
+            DownloadOutcome::SkippedByPolicy { reason } | DownloadOutcome::Failed { reason } => {
+                reason.clone()
+            }
+        }
+    }
+}
+
+/// The outcome of one requested item, along with what it was.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadReport {
+    pub model_id: String,
+    pub model_name: Option<String>,
+    pub model_version_id: Option<i64>,
+    pub file_name: Option<String>,
+    pub outcome: DownloadOutcome,
+}
+
+impl DownloadReport {
+    pub fn new(
+        model: &Model,
+        model_version: Option<&ModelVersion>,
+        file_name: Option<String>,
+        outcome: DownloadOutcome,
+    ) -> Self {
+        DownloadReport {
+            model_id: model.id.to_string(),
+            model_name: Some(model.name.clone()),
+            model_version_id: model_version.map(|v| v.id),
+            file_name,
+            outcome,
+        }
+    }
+
+    /// A report for a model that couldn't even be looked up.
+    pub fn for_model_id(model_id: &str, outcome: DownloadOutcome) -> Self {
+        DownloadReport {
+            model_id: model_id.to_string(),
+            model_name: None,
+            model_version_id: None,
+            file_name: None,
+            outcome,
+        }
+    }
+}
+
+/// An end-of-run table of every [`DownloadReport`].
+pub struct Summary<'a>(pub &'a [DownloadReport]);
+
+impl Summary<'_> {
+    pub fn has_failures(&self) -> bool {
+        self.0.iter().any(|r| r.outcome.is_failure())
+    }
+
+    pub fn has_hash_mismatches(&self) -> bool {
+        self.0
+            .iter()
+            .any(|r| matches!(r.outcome, DownloadOutcome::HashMismatch { .. }))
+    }
+}
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = ["Status", "Model", "Version", "File", "Details"];
+        let rows = self
+            .0
+            .iter()
+            .map(|r| {
+                [
+                    r.outcome.status().to_string(),
+                    match &r.model_name {
+                        Some(name) => format!("{} ({})", r.model_id, name),
+                        None => r.model_id.clone(),
+                    },
+                    r.model_version_id
+                        .map(|id| id.to_string())
+                        .unwrap_or_else(|| "-".to_string()),
+                    r.file_name.clone().unwrap_or_else(|| "-".to_string()),
+                    r.outcome.details(),
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        write_table(f, &header, &rows)?;
+
+        let count = |status: &str| rows.iter().filter(|r| r[0] == status).count();
+        writeln!(
+            f,
+            "\n{} downloaded, {} already present, {} skipped, {} hash mismatches, {} failed",
+            count("downloaded"),
+            count("present"),
+            count("skipped"),
+            count("MISMATCH"),
+            count("FAILED")
+        )
+    }
+}
+
+/// Writes `rows` as left-aligned columns under `header`.
+pub(crate) fn write_table<const N: usize>(
+    f: &mut fmt::Formatter<'_>,
+    header: &[&str; N],
+    rows: &[[String; N]],
+) -> fmt::Result {
+    let mut widths = header.map(str::len);
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let write_row = |f: &mut fmt::Formatter<'_>, cells: &[&str]| {
+        let line = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(f, "{}", line.trim_end())
+    };
+
+    write_row(f, header)?;
+    for row in rows {
+        write_row(f, &row.iter().map(String::as_str).collect::<Vec<_>>())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use anyhow::Context;
+
+    use super::*;
+    use crate::hashing;
+    use crate::model::model_version::Hashes;
+
+    #[test]
+    fn reports_tampered_files_as_hash_mismatches() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"tampered").unwrap();
+        let reported = Hashes {
+            sha256: Some(
+                "2CF24DBA5FB0A30E26E83B2AC5B9E29E1B161E5C1FA7425E73043362938B9824".to_string(),
+            ),
+            ..Default::default()
+        };
+        let error = hashing::verify_file(file.path(), &reported)
+            .context("Failed to check existing file")
+            .unwrap_err();
+
+        let outcome = DownloadOutcome::failed(&error);
+        let DownloadOutcome::HashMismatch { path, kind, expected, .. } = &outcome else {
+            panic!("expected a hash mismatch, got {outcome:?}");
+        };
+        assert_eq!(path, file.path());
+        assert_eq!(*kind, HashKind::Sha256);
+        assert!(expected.starts_with("2cf24dba"));
+        assert!(outcome.is_failure());
+        assert_eq!(outcome.status(), "MISMATCH");
+
+        let outcome = DownloadOutcome::failed(&anyhow::anyhow!("connection reset"));
+        assert!(matches!(outcome, DownloadOutcome::Failed { .. }));
+    }
+}
@@ -0,0 +1,340 @@
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, SystemTime};
+
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Response, StatusCode};
+use tracing::{debug, warn};
+
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+pub const DEFAULT_BASE_DELAY_MS: u64 = 1000;
+pub const DEFAULT_MAX_DELAY_MS: u64 = 60_000;
+pub const DEFAULT_MAX_RETRY_AFTER_MS: u64 = 15 * 60_000;
+
+/// How failed requests and downloads are retried.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// The total number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// The delay before the first retry. Each further retry doubles it.
+    pub base_delay: Duration,
+    /// The longest delay between two attempts when backing off on our own.
+    pub max_delay: Duration,
+    /// The longest `Retry-After` from the server to wait out. Servers asking for longer than this
+    /// are given up on rather than waited for.
+    pub max_retry_after: Duration,
+    /// Whether to randomize each delay so parallel downloads don't retry in lockstep.
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            base_delay: Duration::from_millis(DEFAULT_BASE_DELAY_MS),
+            max_delay: Duration::from_millis(DEFAULT_MAX_DELAY_MS),
+            max_retry_after: Duration::from_millis(DEFAULT_MAX_RETRY_AFTER_MS),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns how long to wait after the given (1-based) failed attempt, or `None` if the server
+    /// asked to wait longer than `max_retry_after`.
+    pub fn get_delay(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if let Some(retry_after) = retry_after {
+            return (retry_after <= self.max_retry_after).then_some(retry_after);
+        }
+        let exponent = attempt.saturating_sub(1).min(16);
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.pow(exponent))
+            .min(self.max_delay);
+        if self.jitter {
+            Some(delay.mul_f64(0.5 + fastrand::f64() / 2.0))
+        } else {
+            Some(delay)
+        }
+    }
+
+    /// Runs `operation` until it succeeds, fails permanently, or runs out of attempts.
+    pub async fn run<T, F, Fut>(&self, description: &str, mut operation: F) -> anyhow::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation().await {
+                Ok(o) => return Ok(o),
+                Err(e) if attempt < self.max_attempts && is_transient(&e) => {
+                    let retry_after = get_retry_after(&e);
+                    let Some(delay) = self.get_delay(attempt, retry_after) else {
+                        warn!(
+                            retry_after =? retry_after,
+                            max_retry_after =? self.max_retry_after,
+                            "{} failed and the server asked to wait too long. Giving up ...",
+                            description
+                        );
+                        return Err(e);
+                    };
+                    warn!(
+                        error =? e,
+                        attempt,
+                        max_attempts = self.max_attempts,
+                        "{} failed. Retrying in {:.1}s ...",
+                        description,
+                        delay.as_secs_f64()
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// A failure that is expected to go away if the operation is retried.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransientError {
+    pub message: String,
+    pub retry_after: Option<Duration>,
+}
+
+impl fmt::Display for TransientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for TransientError {}
+
+/// Whether `error`, or anything that caused it, is worth retrying.
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if cause.is::<TransientError>() {
+            return true;
+        }
+        match cause.downcast_ref::<reqwest::Error>() {
+            Some(e) => {
+                e.is_timeout()
+                    || e.is_connect()
+                    || e.is_body()
+                    || e.status().map(is_transient_status).unwrap_or(false)
+            }
+            None => false,
+        }
+    })
+}
+
+fn get_retry_after(error: &anyhow::Error) -> Option<Duration> {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<TransientError>()?.retry_after)
+}
+
+pub fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT
+            | StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Turns an unsuccessful response into an error, marking it as transient when the status says
+/// the request may succeed later.
+pub fn check_response(response: Response) -> anyhow::Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let message = format!("'{}' returned {}", response.url(), status);
+    if is_transient_status(status) {
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after);
+        debug!(status =? status, retry_after =? retry_after, "Transient response");
+        Err(TransientError {
+            message,
+            retry_after,
+        }
+        .into())
+    } else {
+        Err(anyhow::anyhow!(message))
+    }
+}
+
+/// Parses a `Retry-After` value given either in seconds or as an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use anyhow::Context;
+
+    use super::*;
+
+    fn get_policy(jitter: bool) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(10),
+            max_retry_after: Duration::from_secs(60),
+            jitter,
+        }
+    }
+
+    fn get_response(status: u16, retry_after: Option<&str>) -> Response {
+        let mut response = http::Response::builder().status(status);
+        if let Some(value) = retry_after {
+            response = response.header(RETRY_AFTER, value);
+        }
+        Response::from(response.body("").unwrap())
+    }
+
+    #[test]
+    fn parses_retry_after_seconds() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 5 "), Some(Duration::from_secs(5)));
+        assert_eq!(parse_retry_after("0"), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn parses_retry_after_dates() {
+        let later = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(90));
+        let delay = parse_retry_after(&later).unwrap();
+        assert!(delay > Duration::from_secs(85) && delay <= Duration::from_secs(90));
+
+        let earlier = httpdate::fmt_http_date(SystemTime::now() - Duration::from_secs(90));
+        assert_eq!(parse_retry_after(&earlier), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn ignores_bad_retry_after() {
+        for value in ["", "soon", "-1", "1.5", "Someday, 99 Foo 2024"] {
+            assert_eq!(parse_retry_after(value), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_max_delay() {
+        let policy = get_policy(false);
+        let delays = (1..=6)
+            .map(|attempt| policy.get_delay(attempt, None).unwrap().as_secs())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [1, 2, 4, 8, 10, 10]);
+        assert_eq!(policy.get_delay(1000, None), Some(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn jitter_stays_within_half_and_full_delay() {
+        let policy = get_policy(true);
+        for attempt in 1..=6 {
+            let full = get_policy(false).get_delay(attempt, None).unwrap();
+            for _ in 0..100 {
+                let delay = policy.get_delay(attempt, None).unwrap();
+                assert!(delay >= full / 2 && delay <= full, "{delay:?} for {full:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn honors_retry_after_up_to_its_cap() {
+        let policy = get_policy(true);
+        let retry_after = Some(Duration::from_secs(45));
+        assert_eq!(policy.get_delay(1, retry_after), retry_after);
+        assert_eq!(policy.get_delay(1, Some(Duration::from_secs(61))), None);
+    }
+
+    #[test]
+    fn classifies_statuses() {
+        for status in [408, 429, 500, 502, 503, 504] {
+            assert!(is_transient_status(StatusCode::from_u16(status).unwrap()), "{status}");
+        }
+        for status in [400, 401, 403, 404, 416, 501] {
+            assert!(!is_transient_status(StatusCode::from_u16(status).unwrap()), "{status}");
+        }
+    }
+
+    #[test]
+    fn checks_responses() {
+        assert!(check_response(get_response(200, None)).is_ok());
+
+        let error = check_response(get_response(429, Some("30"))).unwrap_err();
+        assert!(is_transient(&error));
+        assert_eq!(get_retry_after(&error), Some(Duration::from_secs(30)));
+
+        let error = check_response(get_response(503, None)).unwrap_err();
+        assert!(is_transient(&error));
+        assert_eq!(get_retry_after(&error), None);
+
+        let error = check_response(get_response(404, Some("30"))).unwrap_err();
+        assert!(!is_transient(&error));
+    }
+
+    #[test]
+    fn finds_transient_errors_behind_context() {
+        let error = anyhow::Error::from(TransientError {
+            message: "ended early".to_string(),
+            retry_after: None,
+        })
+        .context("Failed to download");
+        assert!(is_transient(&error));
+        assert!(!is_transient(&anyhow::anyhow!("bad request")));
+    }
+
+    #[tokio::test]
+    async fn retries_connect_and_timeout_errors() {
+        let client = reqwest::Client::builder()
+            .no_proxy()
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let is_reqwest = |error: &anyhow::Error, check: fn(&reqwest::Error) -> bool| {
+            error
+                .chain()
+                .any(|cause| cause.downcast_ref::<reqwest::Error>().is_some_and(check))
+        };
+
+        // Nothing listens on a port that was just released.
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let error = client
+            .get(format!("http://127.0.0.1:{port}/"))
+            .send()
+            .await
+            .context("Failed to GET")
+            .unwrap_err();
+        assert!(is_reqwest(&error, reqwest::Error::is_connect), "{error:?}");
+        assert!(is_transient(&error));
+
+        // A listener that never answers.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let error = client
+            .get(format!("http://{}/", listener.local_addr().unwrap()))
+            .send()
+            .await
+            .context("Failed to GET")
+            .unwrap_err();
+        assert!(is_reqwest(&error, reqwest::Error::is_timeout), "{error:?}");
+        assert!(is_transient(&error));
+    }
+}
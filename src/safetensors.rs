@@ -0,0 +1,388 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use indicatif::HumanBytes;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::debug;
+
+use crate::report::write_table;
+
+/// The largest header the reference implementation accepts.
+const MAX_HEADER_SIZE: u64 = 100 * 1024 * 1024;
+const METADATA_KEY: &str = "__metadata__";
+
+/// The description of a single tensor in a safetensors header.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TensorInfo {
+    pub dtype: String,
+    pub shape: Vec<u64>,
+    pub data_offsets: [u64; 2],
+}
+
+impl TensorInfo {
+    pub fn parameter_count(&self) -> u64 {
+        self.shape.iter().fold(1, |count, d| count.saturating_mul(*d))
+    }
+}
+
+/// The parsed header of a safetensors file.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Header {
+    pub header_size: u64,
+    pub tensors: BTreeMap<String, TensorInfo>,
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// Returned (wrapped in an [`anyhow::Error`]) when a file is not a well-formed safetensors file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidSafetensors {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidSafetensors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "'{}' is not a valid safetensors file: {}",
+            self.path.to_string_lossy(),
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for InvalidSafetensors {}
+
+/// What [`inspect`] found in a safetensors file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Inspection {
+    pub path: PathBuf,
+    pub file_size: u64,
+    pub tensor_count: usize,
+    pub parameter_count: u64,
+    /// How many tensors use each dtype.
+    pub dtypes: BTreeMap<String, usize>,
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.path.to_string_lossy())?;
+        writeln!(f, "  Size:       {}", HumanBytes(self.file_size))?;
+        writeln!(f, "  Tensors:    {}", self.tensor_count)?;
+        writeln!(f, "  Parameters: {}", self.parameter_count)?;
+        let dtypes = self
+            .dtypes
+            .iter()
+            .map(|(dtype, count)| format!("{dtype} ({count})"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(f, "  Dtypes:     {dtypes}")?;
+        if self.metadata.is_empty() {
+            return Ok(());
+        }
+        writeln!(f, "\nMetadata:")?;
+        let rows = self
+            .metadata
+            .iter()
+            .map(|(key, value)| [key.clone(), truncate(value)])
+            .collect::<Vec<_>>();
+        write_table(f, &["Key", "Value"], &rows)
+    }
+}
+
+/// Long values such as `ss_tag_frequency` would drown out everything else.
+fn truncate(value: &str) -> String {
+    const MAX_CHARS: usize = 120;
+    match value.char_indices().nth(MAX_CHARS) {
+        Some((end, _)) => format!("{}...", &value[..end]),
+        None => value.to_string(),
+    }
+}
+
+/// The size in bits of one element of `dtype`, or `None` if it isn't a safetensors dtype.
+///
+/// Bits rather than bytes, as the FP4 and FP6 types pack several elements into a byte.
+pub fn get_dtype_bits(dtype: &str) -> Option<u64> {
+    let bits = match dtype {
+        "F4" => 4,
+        "F6_E2M3" | "F6_E3M2" => 6,
+        "BOOL" | "U8" | "I8" | "F8_E5M2" | "F8_E4M3" | "F8_E8M0" => 8,
+        "I16" | "U16" | "F16" | "BF16" => 16,
+        "I32" | "U32" | "F32" => 32,
+        "I64" | "U64" | "F64" | "C64" => 64,
+        _ => return None,
+    };
+    Some(bits)
+}
+
+/// Reads and parses the header of the safetensors file at `path` without validating it.
+pub fn read_header(path: &Path) -> anyhow::Result<Header> {
+    let invalid = |reason: String| InvalidSafetensors {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open '{}'", path.to_string_lossy()))?;
+    let file_size = file.metadata()?.len();
+
+    let mut size_bytes = [0u8; 8];
+    file.read_exact(&mut size_bytes)
+        .map_err(|_| invalid(format!("only {file_size} bytes long")))?;
+    if is_html(&size_bytes) {
+        return Err(invalid("looks like an HTML page".to_string()).into());
+    }
+    let header_size = u64::from_le_bytes(size_bytes);
+    if header_size > MAX_HEADER_SIZE || header_size > file_size - 8 {
+        return Err(invalid(format!(
+            "header claims {header_size} bytes but the file is {file_size} bytes"
+        ))
+        .into());
+    }
+
+    let mut header_bytes = vec![0u8; header_size as usize];
+    file.read_exact(&mut header_bytes)?;
+    let entries: BTreeMap<String, Value> = serde_json::from_slice(&header_bytes)
+        .map_err(|e| invalid(format!("header is not a JSON object: {e}")))?;
+
+    let mut header = Header {
+        header_size,
+        ..Default::default()
+    };
+    for (name, value) in entries {
+        if name == METADATA_KEY {
+            header.metadata = serde_json::from_value(value)
+                .map_err(|e| invalid(format!("{METADATA_KEY} is not a map of strings: {e}")))?;
+        } else {
+            let tensor = serde_json::from_value(value)
+                .map_err(|e| invalid(format!("tensor '{name}' is malformed: {e}")))?;
+            header.tensors.insert(name, tensor);
+        }
+    }
+    Ok(header)
+}
+
+/// Writes the size and JSON header of a safetensors file, padded with spaces so the data that
+/// follows is 8-byte aligned. Returns the number of bytes written.
+pub fn write_header(
+    writer: &mut impl Write,
+    tensors: &BTreeMap<String, TensorInfo>,
+    metadata: &BTreeMap<String, String>,
+) -> anyhow::Result<u64> {
+    let mut entries = serde_json::Map::new();
+    if !metadata.is_empty() {
+        entries.insert(METADATA_KEY.to_string(), serde_json::to_value(metadata)?);
+    }
+    for (name, tensor) in tensors {
+        entries.insert(name.clone(), serde_json::to_value(tensor)?);
+    }
+    let mut header = serde_json::to_vec(&entries)?;
+    header.resize(header.len().next_multiple_of(8), b' ');
+    writer.write_all(&(header.len() as u64).to_le_bytes())?;
+    writer.write_all(&header)?;
+    Ok(8 + header.len() as u64)
+}
+
+/// A real header size has its high bytes zeroed, so printable text starting with `<` is an error
+/// page served in place of the file.
+fn is_html(start: &[u8]) -> bool {
+    let text = start.trim_ascii_start();
+    text.starts_with(b"<") && start.iter().all(|b| b.is_ascii_graphic() || b.is_ascii_whitespace())
+}
+
+/// Checks that every tensor has a known dtype, that its byte range matches its shape, and that
+/// the tensors cover the data section of the file exactly.
+pub fn validate(path: &Path, header: &Header, file_size: u64) -> anyhow::Result<()> {
+    let invalid = |reason: String| InvalidSafetensors {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let data_size = file_size.saturating_sub(8 + header.header_size);
+
+    let mut ranges = Vec::with_capacity(header.tensors.len());
+    for (name, tensor) in &header.tensors {
+        let bits = get_dtype_bits(&tensor.dtype)
+            .ok_or_else(|| invalid(format!("tensor '{name}' has unknown dtype {}", tensor.dtype)))?;
+        let [begin, end] = tensor.data_offsets;
+        let expected_bits = tensor.parameter_count().saturating_mul(bits);
+        if end < begin || (end - begin).saturating_mul(8) != expected_bits {
+            return Err(invalid(format!(
+                "tensor '{name}' spans bytes {begin}..{end}, which doesn't fit a {} {:?}",
+                tensor.dtype, tensor.shape
+            ))
+            .into());
+        }
+        ranges.push((begin, end, name));
+    }
+
+    ranges.sort();
+    let mut covered = 0;
+    for (begin, end, name) in ranges {
+        if begin != covered {
+            return Err(invalid(format!(
+                "tensor '{name}' starts at byte {begin} instead of {covered}"
+            ))
+            .into());
+        }
+        covered = end;
+    }
+    if covered != data_size {
+        return Err(invalid(format!(
+            "tensors cover {covered} bytes of data but the file has {data_size}, it may be truncated"
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Parses and validates the safetensors file at `path`, summarizing its contents.
+#[tracing::instrument(level = "debug")]
+pub fn inspect(path: &Path) -> anyhow::Result<Inspection> {
+    let header = read_header(path)?;
+    let file_size = path.metadata()?.len();
+    validate(path, &header, file_size)?;
+
+    let mut dtypes = BTreeMap::new();
+    for tensor in header.tensors.values() {
+        *dtypes.entry(tensor.dtype.clone()).or_insert(0) += 1;
+    }
+    let inspection = Inspection {
+        path: path.to_path_buf(),
+        file_size,
+        tensor_count: header.tensors.len(),
+        parameter_count: header.tensors.values().map(TensorInfo::parameter_count).sum(),
+        dtypes,
+        metadata: header.metadata,
+    };
+    debug!(
+        tensors = inspection.tensor_count,
+        parameters = inspection.parameter_count,
+        "Inspected safetensors file"
+    );
+    Ok(inspection)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    fn tensor(dtype: &str, shape: &[u64], begin: u64, end: u64) -> (String, TensorInfo) {
+        let info = TensorInfo {
+            dtype: dtype.to_string(),
+            shape: shape.to_vec(),
+            data_offsets: [begin, end],
+        };
+        (format!("t{begin}"), info)
+    }
+
+    fn write_file(tensors: &[(String, TensorInfo)], data_size: usize) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        let tensors = tensors.iter().cloned().collect();
+        let metadata = BTreeMap::from([("format".to_string(), "pt".to_string())]);
+        write_header(&mut file, &tensors, &metadata).unwrap();
+        file.write_all(&vec![0; data_size]).unwrap();
+        file
+    }
+
+    fn write_raw(bytes: &[u8]) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(bytes).unwrap();
+        file
+    }
+
+    fn get_reason(file: &NamedTempFile) -> String {
+        let error = inspect(file.path()).unwrap_err();
+        error
+            .downcast_ref::<InvalidSafetensors>()
+            .unwrap_or_else(|| panic!("unexpected error: {error:#}"))
+            .reason
+            .clone()
+    }
+
+    #[test]
+    fn inspects_valid_file() {
+        let file = write_file(
+            &[tensor("F32", &[2, 3], 0, 24), tensor("F4", &[4], 24, 26)],
+            26,
+        );
+        let inspection = inspect(file.path()).unwrap();
+        assert_eq!(inspection.tensor_count, 2);
+        assert_eq!(inspection.parameter_count, 10);
+        assert_eq!(inspection.dtypes.get("F32"), Some(&1));
+        assert_eq!(inspection.metadata.get("format").map(String::as_str), Some("pt"));
+    }
+
+    #[test]
+    fn aligns_header() {
+        let mut header = Vec::new();
+        let tensors = BTreeMap::from([tensor("U8", &[1], 0, 1)]);
+        let written = write_header(&mut header, &tensors, &BTreeMap::new()).unwrap();
+        assert_eq!(written, header.len() as u64);
+        assert_eq!(header.len() % 8, 0);
+    }
+
+    #[test]
+    fn rejects_truncated_file() {
+        let file = write_file(&[tensor("F16", &[8], 0, 16)], 10);
+        assert!(get_reason(&file).contains("may be truncated"));
+        assert!(get_reason(&write_raw(&[1, 0, 0, 0])).contains("only 4 bytes long"));
+    }
+
+    #[test]
+    fn rejects_html_page() {
+        let file = write_raw(b"<!DOCTYPE html><html><body>Log in</body></html>");
+        assert!(get_reason(&file).contains("HTML"));
+        let file = write_raw(b"\n  <html><head></head></html>");
+        assert!(get_reason(&file).contains("HTML"));
+    }
+
+    #[test]
+    fn rejects_overlapping_tensors() {
+        let file = write_file(&[tensor("F32", &[2], 0, 8), tensor("F32", &[2], 4, 12)], 12);
+        assert!(get_reason(&file).contains("starts at byte 4 instead of 8"));
+    }
+
+    #[test]
+    fn rejects_gaps_between_tensors() {
+        let file = write_file(&[tensor("F32", &[2], 0, 8), tensor("F32", &[2], 16, 24)], 24);
+        assert!(get_reason(&file).contains("starts at byte 16 instead of 8"));
+    }
+
+    #[test]
+    fn rejects_ranges_not_fitting_shape() {
+        let file = write_file(&[tensor("F32", &[3], 0, 8)], 8);
+        assert!(get_reason(&file).contains("doesn't fit"));
+        let file = write_file(&[tensor("F32", &[0], 8, 0)], 0);
+        assert!(get_reason(&file).contains("doesn't fit"));
+    }
+
+    #[test]
+    fn rejects_unknown_dtype() {
+        let file = write_file(&[tensor("Q4_K", &[2], 0, 1)], 1);
+        assert!(get_reason(&file).contains("unknown dtype Q4_K"));
+    }
+
+    #[test]
+    fn rejects_header_larger_than_file() {
+        let mut bytes = 1000u64.to_le_bytes().to_vec();
+        bytes.extend_from_slice(b"{}");
+        assert!(get_reason(&write_raw(&bytes)).contains("header claims 1000 bytes"));
+        let mut bytes = (MAX_HEADER_SIZE + 1).to_le_bytes().to_vec();
+        bytes.extend_from_slice(b"{}");
+        assert!(get_reason(&write_raw(&bytes)).contains("header claims"));
+    }
+
+    #[test]
+    fn rejects_malformed_header() {
+        let mut bytes = 4u64.to_le_bytes().to_vec();
+        bytes.extend_from_slice(b"[1]x");
+        assert!(get_reason(&write_raw(&bytes)).contains("not a JSON object"));
+    }
+}
@@ -0,0 +1,206 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, warn};
+
+use crate::model::model_version::{ModelVersion, ResourceFile};
+use crate::model::Model;
+use crate::report::write_table;
+
+/// Extensions of the files worth identifying.
+pub const MODEL_EXTENSIONS: [&str; 5] = ["safetensors", "ckpt", "pt", "pth", "bin"];
+
+/// What scanning a local file found.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScanOutcome {
+    /// The file is already in the manifest.
+    Known,
+    Identified {
+        model: Box<Model>,
+        model_version: Box<ModelVersion>,
+        file: Box<ResourceFile>,
+    },
+    Unmatched,
+    Failed { reason: String },
+}
+
+impl ScanOutcome {
+    pub fn failed(error: &anyhow::Error) -> Self {
+        ScanOutcome::Failed {
+            reason: format!("{error:#}"),
+        }
+    }
+}
+
+/// A local file and what scanning it found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanResult {
+    pub path: PathBuf,
+    pub sha256: Option<String>,
+    pub outcome: ScanOutcome,
+}
+
+/// Lists the model files in `paths`, descending into directories but skipping anything under
+/// `excluded`, such as the quarantine directory.
+pub fn find_model_files(paths: &[PathBuf], excluded: &[PathBuf]) -> Vec<PathBuf> {
+    let excluded = excluded
+        .iter()
+        .filter_map(|p| p.canonicalize().ok())
+        .collect::<Vec<_>>();
+    let mut found = Vec::new();
+    for path in paths {
+        let is_excluded = path
+            .canonicalize()
+            .is_ok_and(|p| excluded.iter().any(|e| p.starts_with(e)));
+        if is_excluded {
+            debug!("Skipping excluded {}", path.to_string_lossy());
+            continue;
+        }
+        collect_model_files(path, &excluded, &mut found);
+    }
+    found.sort();
+    found.dedup();
+    found
+}
+
+fn collect_model_files(path: &Path, excluded: &[PathBuf], found: &mut Vec<PathBuf>) {
+    if path.is_file() {
+        if is_model_file(path) {
+            found.push(path.to_path_buf());
+        }
+        return;
+    }
+    if path.canonicalize().is_ok_and(|p| excluded.contains(&p)) {
+        debug!("Skipping excluded directory {}", path.to_string_lossy());
+        return;
+    }
+    let entries = match fs::read_dir(path) {
+        Ok(e) => e,
+        Err(e) => {
+            warn!(error =? e, "Failed to read directory {}", path.to_string_lossy());
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        match entry.file_type() {
+            // Linked directories can form cycles, so only linked files are picked up.
+            Ok(file_type) if file_type.is_symlink() => {
+                if path.is_file() && is_model_file(&path) {
+                    found.push(path);
+                } else {
+                    debug!("Not following link {}", path.to_string_lossy());
+                }
+            }
+            Ok(_) => collect_model_files(&path, excluded, found),
+            Err(e) => warn!(error =? e, "Failed to read the type of {}", path.to_string_lossy()),
+        }
+    }
+}
+
+fn is_model_file(path: &Path) -> bool {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .is_some_and(|e| MODEL_EXTENSIONS.contains(&e.as_str()))
+}
+
+/// A table of [`ScanResult`]s, followed by the files that couldn't be identified.
+pub struct ScanSummary<'a>(pub &'a [ScanResult]);
+
+impl ScanSummary<'_> {
+    pub fn has_failures(&self) -> bool {
+        self.0
+            .iter()
+            .any(|r| matches!(r.outcome, ScanOutcome::Failed { .. }))
+    }
+}
+
+impl fmt::Display for ScanSummary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = ["Status", "File", "Details"];
+        let rows = self
+            .0
+            .iter()
+            .map(|r| {
+                let (status, details) = match &r.outcome {
+                    ScanOutcome::Known => ("known", "-".to_string()),
+                    ScanOutcome::Identified {
+                        model,
+                        model_version,
+                        ..
+                    } => (
+                        "identified",
+                        format!(
+                            "{} ({}), version {} ({})",
+                            model.id, model.name, model_version.id, model_version.name
+                        ),
+                    ),
+                    ScanOutcome::Unmatched => ("unmatched", "-".to_string()),
+                    ScanOutcome::Failed { reason } => ("FAILED", reason.clone()),
+                };
+                [
+                    status.to_string(),
+                    r.path.to_string_lossy().to_string(),
+                    details,
+                ]
+            })
+            .collect::<Vec<_>>();
+        write_table(f, &header, &rows)?;
+
+        let count = |status: &str| rows.iter().filter(|r| r[0] == status).count();
+        writeln!(
+            f,
+            "\n{} identified, {} already known, {} unmatched, {} failed",
+            count("identified"),
+            count("known"),
+            count("unmatched"),
+            count("FAILED")
+        )?;
+        let unmatched = self
+            .0
+            .iter()
+            .filter(|r| r.outcome == ScanOutcome::Unmatched)
+            .collect::<Vec<_>>();
+        if !unmatched.is_empty() {
+            writeln!(f, "\nUnmatched files:")?;
+            for r in unmatched {
+                writeln!(
+                    f,
+                    "  {} (SHA256 {})",
+                    r.path.to_string_lossy(),
+                    r.sha256.as_deref().unwrap_or("?")
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_excluded_directories() {
+        let root = tempfile::tempdir().unwrap();
+        let quarantine = root.path().join("quarantine").join("1").join("2");
+        fs::create_dir_all(&quarantine).unwrap();
+        fs::create_dir_all(root.path().join("Lora")).unwrap();
+        fs::write(quarantine.join("flagged.ckpt"), b"").unwrap();
+        fs::write(root.path().join("Lora").join("kept.safetensors"), b"").unwrap();
+        fs::write(root.path().join("notes.txt"), b"").unwrap();
+
+        let found = find_model_files(
+            &[root.path().to_path_buf()],
+            &[root.path().join("quarantine")],
+        );
+        assert_eq!(found, vec![root.path().join("Lora").join("kept.safetensors")]);
+
+        let found = find_model_files(
+            &[quarantine.join("flagged.ckpt")],
+            &[root.path().join("quarantine")],
+        );
+        assert!(found.is_empty());
+    }
+}
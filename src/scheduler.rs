@@ -0,0 +1,81 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::debug;
+
+pub const DEFAULT_MAX_CONCURRENT_DOWNLOADS: usize = 2;
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 4;
+
+/// Bounds how many downloads and API requests run at once.
+///
+/// Clones share the same limits, so every clone of a [`crate::Civit`] draws from one pool.
+#[derive(Clone, Debug)]
+pub struct Scheduler {
+    downloads: Arc<Semaphore>,
+    requests: Arc<Semaphore>,
+}
+
+impl Scheduler {
+    pub fn new(max_concurrent_downloads: usize, max_concurrent_requests: usize) -> Self {
+        debug!(
+            max_concurrent_downloads,
+            max_concurrent_requests, "Constructing scheduler"
+        );
+        Scheduler {
+            downloads: Arc::new(Semaphore::new(max_concurrent_downloads.max(1))),
+            requests: Arc::new(Semaphore::new(max_concurrent_requests.max(1))),
+        }
+    }
+
+    /// Waits for a free metadata request slot.
+    pub async fn acquire_request(&self) -> anyhow::Result<OwnedSemaphorePermit> {
+        Ok(self.requests.clone().acquire_owned().await?)
+    }
+
+    /// Waits for a free download slot, showing `label` as pending in `multi_progress` while the
+    /// download is queued.
+    pub async fn acquire_download(
+        &self,
+        multi_progress: &MultiProgress,
+        label: &str,
+    ) -> anyhow::Result<OwnedSemaphorePermit> {
+        if let Ok(permit) = self.downloads.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+
+        debug!("Queueing download of {}", label);
+        let pending = multi_progress.add(
+            ProgressBar::new_spinner()
+                .with_message(format!("Pending: {label}"))
+                .with_style(ProgressStyle::default_spinner().template("{spinner:.yellow} {msg}")?),
+        );
+        pending.enable_steady_tick(Duration::from_millis(250));
+        let permit = self.downloads.clone().acquire_owned().await;
+        pending.finish_and_clear();
+        multi_progress.remove(&pending);
+        Ok(permit?)
+    }
+
+    /// Takes up to `extra` more download slots without waiting, for a download that wants to open
+    /// more than one connection. Returns how many were taken, or `None` if every slot is taken.
+    pub fn try_acquire_extra_downloads(
+        &self,
+        extra: usize,
+    ) -> Option<(usize, OwnedSemaphorePermit)> {
+        (1..=extra as u32).rev().find_map(|n| {
+            let permit = self.downloads.clone().try_acquire_many_owned(n).ok()?;
+            Some((n as usize, permit))
+        })
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler::new(
+            DEFAULT_MAX_CONCURRENT_DOWNLOADS,
+            DEFAULT_MAX_CONCURRENT_REQUESTS,
+        )
+    }
+}
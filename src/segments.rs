@@ -0,0 +1,183 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use blake3::hazmat::ChainingValue;
+use serde::{Deserialize, Serialize};
+
+use crate::hashing::BLAKE3_BLOCK_SIZE;
+
+/// One byte range of a segmented download, along with the BLAKE3 blocks fetched so far.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    pub start: u64,
+    /// The last byte of the segment, as in a `Range` header.
+    pub end: u64,
+    /// Chaining values of the blocks written so far, in order.
+    #[serde(with = "hex_blocks")]
+    pub blocks: Vec<ChainingValue>,
+    /// Set once the whole range is written, since the last block of a file may be short.
+    pub done: bool,
+}
+
+impl Segment {
+    /// The byte fetching the segment picks up from.
+    pub fn position(&self) -> u64 {
+        if self.done {
+            self.end + 1
+        } else {
+            self.start + self.blocks.len() as u64 * BLAKE3_BLOCK_SIZE
+        }
+    }
+
+    fn is_consistent(&self) -> bool {
+        if !self.start.is_multiple_of(BLAKE3_BLOCK_SIZE) || self.start > self.end {
+            return false;
+        }
+        let blocks = (self.end + 1 - self.start).div_ceil(BLAKE3_BLOCK_SIZE);
+        if self.done {
+            self.blocks.len() as u64 == blocks
+        } else {
+            (self.blocks.len() as u64) < blocks
+        }
+    }
+}
+
+/// The progress of a download split into parallel byte ranges, saved next to it so an
+/// interrupted download only fetches the ranges it is missing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SegmentedDownload {
+    pub total_size: u64,
+    pub segments: Vec<Segment>,
+}
+
+impl SegmentedDownload {
+    /// Splits `total_size` bytes into up to `connections` segments that start on BLAKE3 block
+    /// boundaries, so each can be hashed on its own.
+    pub fn new(total_size: u64, connections: usize) -> Self {
+        let segment_size = total_size
+            .div_ceil(connections.max(1) as u64)
+            .next_multiple_of(BLAKE3_BLOCK_SIZE);
+        let segments = (0..total_size)
+            .step_by(segment_size as usize)
+            .map(|start| Segment {
+                start,
+                end: (start + segment_size).min(total_size) - 1,
+                blocks: Vec::new(),
+                done: false,
+            })
+            .collect();
+        SegmentedDownload {
+            total_size,
+            segments,
+        }
+    }
+
+    /// Loads the progress saved at `path`, failing unless it is for a file of `total_size` bytes.
+    pub fn load(path: &Path, total_size: u64) -> anyhow::Result<Self> {
+        let contents = std::fs::read(path)
+            .with_context(|| format!("Failed to read '{}'", path.to_string_lossy()))?;
+        let download: SegmentedDownload = serde_json::from_slice(&contents)
+            .with_context(|| format!("Failed to parse '{}'", path.to_string_lossy()))?;
+        if download.total_size != total_size {
+            return Err(anyhow!(
+                "Saved segments are for {} bytes, not {}",
+                download.total_size,
+                total_size
+            ));
+        }
+        let contiguous = download
+            .segments
+            .iter()
+            .try_fold(0, |next, s| (s.start == next && s.is_consistent()).then_some(s.end + 1));
+        if contiguous != Some(total_size) || total_size == 0 {
+            return Err(anyhow!("Saved segments don't cover the file"));
+        }
+        Ok(download)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_vec(self)?)
+            .with_context(|| format!("Failed to write '{}'", path.to_string_lossy()))
+    }
+
+    /// How many bytes are already written.
+    pub fn fetched(&self) -> u64 {
+        self.segments.iter().map(|s| s.position() - s.start).sum()
+    }
+
+    /// Returns the chaining values of every segment in file order, for
+    /// [`crate::hashing::combine_blake3_segments`].
+    pub fn into_blocks(self) -> Vec<Vec<ChainingValue>> {
+        self.segments.into_iter().map(|s| s.blocks).collect()
+    }
+}
+
+mod hex_blocks {
+    use blake3::hazmat::ChainingValue;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(blocks: &[ChainingValue], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(blocks.iter().map(|cv| blake3::Hash::from(*cv).to_hex().to_string()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<ChainingValue>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|cv| blake3::Hash::from_hex(cv).map(|h| *h.as_bytes()).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_on_block_boundaries() {
+        let download = SegmentedDownload::new(5 * BLAKE3_BLOCK_SIZE + 17, 3);
+        let ranges: Vec<_> = download.segments.iter().map(|s| (s.start, s.end)).collect();
+        assert_eq!(
+            ranges,
+            [
+                (0, 2 * BLAKE3_BLOCK_SIZE - 1),
+                (2 * BLAKE3_BLOCK_SIZE, 4 * BLAKE3_BLOCK_SIZE - 1),
+                (4 * BLAKE3_BLOCK_SIZE, 5 * BLAKE3_BLOCK_SIZE + 16),
+            ]
+        );
+        assert_eq!(download.fetched(), 0);
+    }
+
+    #[test]
+    fn resumes_from_saved_progress() {
+        let total_size = 5 * BLAKE3_BLOCK_SIZE + 17;
+        let mut download = SegmentedDownload::new(total_size, 3);
+        download.segments[0].blocks = vec![[1; 32], [2; 32]];
+        download.segments[0].done = true;
+        download.segments[2].blocks = vec![[3; 32]];
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        download.save(file.path()).unwrap();
+        let loaded = SegmentedDownload::load(file.path(), total_size).unwrap();
+        assert_eq!(loaded, download);
+        assert_eq!(loaded.segments[1].position(), 2 * BLAKE3_BLOCK_SIZE);
+        assert_eq!(loaded.segments[2].position(), 5 * BLAKE3_BLOCK_SIZE);
+        assert_eq!(loaded.fetched(), 3 * BLAKE3_BLOCK_SIZE);
+    }
+
+    #[test]
+    fn rejects_progress_for_other_files() {
+        let total_size = 5 * BLAKE3_BLOCK_SIZE + 17;
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut download = SegmentedDownload::new(total_size, 3);
+        download.save(file.path()).unwrap();
+        assert!(SegmentedDownload::load(file.path(), total_size + 1).is_err());
+
+        // A finished segment must have all of its blocks.
+        download.segments[1].done = true;
+        download.save(file.path()).unwrap();
+        assert!(SegmentedDownload::load(file.path(), total_size).is_err());
+
+        std::fs::write(file.path(), "{").unwrap();
+        assert!(SegmentedDownload::load(file.path(), total_size).is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use serde_json::{Map, Value};
+use tempfile::NamedTempFile;
+use tracing::{debug, trace};
+
+use crate::model::model_version::ModelVersion;
+use crate::model::Model;
+
+/// The path of the `.civitai.info` sidecar of `model_path`, as read by the Civitai Helper
+/// extension.
+pub fn get_civitai_info_path(model_path: &Path) -> PathBuf {
+    with_suffix(model_path, "civitai.info")
+}
+
+/// Builds the `.civitai.info` contents: the model version as returned by the API, with every
+/// field of the parent model except its version list under `model`.
+pub fn build_civitai_info(model: &Model, model_version: &ModelVersion) -> anyhow::Result<Value> {
+    let mut info = serde_json::to_value(model_version)?;
+    let mut parent = serde_json::to_value(model)?;
+    if let Value::Object(fields) = &mut parent {
+        fields.remove("modelVersions");
+    }
+    info.as_object_mut()
+        .ok_or(anyhow!("Model version {} is not an object", model_version.id))?
+        .insert("model".to_string(), parent);
+    Ok(info)
+}
+
+/// Writes the `.civitai.info` sidecar of `model_path`, replacing any existing one.
+pub fn write_civitai_info(
+    model_path: &Path,
+    model: &Model,
+    model_version: &ModelVersion,
+) -> anyhow::Result<PathBuf> {
+    let path = get_civitai_info_path(model_path);
+    write_json(&path, &build_civitai_info(model, model_version)?)?;
+    debug!("Wrote {}", path.to_string_lossy());
+    Ok(path)
+}
+
+/// Reads the model version back from a `.civitai.info` sidecar.
+pub fn read_civitai_info(path: &Path) -> anyhow::Result<ModelVersion> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read '{}'", path.to_string_lossy()))?;
+    serde_json::from_str(&contents)
+        .with_context(|| format!("Failed to parse '{}'", path.to_string_lossy()))
+}
+
+/// The path of the `.json` sidecar A1111 reads extra network card details from.
+pub fn get_extra_network_path(model_path: &Path) -> PathBuf {
+    with_suffix(model_path, "json")
+}
+
+/// Builds the fields of A1111's extra network `.json` sidecar from the model version.
+pub fn build_extra_network_info(model: &Model, model_version: &ModelVersion) -> Map<String, Value> {
+    let description = model_version
+        .description
+        .as_deref()
+        .or(model.description.as_deref())
+        .map(strip_html)
+        .unwrap_or_default();
+    let mut fields = Map::new();
+    fields.insert("description".to_string(), Value::from(description));
+    fields.insert(
+        "sd version".to_string(),
+        Value::from(get_sd_version(model_version.base_model.as_deref())),
+    );
+    fields.insert(
+        "activation text".to_string(),
+        Value::from(model_version.trained_words.join(", ")),
+    );
+    fields.insert("preferred weight".to_string(), Value::from(0));
+    fields.insert("notes".to_string(), Value::from(""));
+    fields
+}
+
+/// Writes the extra network `.json` sidecar of `model_path`.
+///
+/// Fields already in an existing sidecar are kept unless they are empty, so edits made in the
+/// UI survive re-downloads. A sidecar that isn't a JSON object is left alone.
+pub fn write_extra_network_info(
+    model_path: &Path,
+    model: &Model,
+    model_version: &ModelVersion,
+) -> anyhow::Result<PathBuf> {
+    let path = get_extra_network_path(model_path);
+    let mut merged = match fs::read_to_string(&path) {
+        Ok(contents) => match serde_json::from_str::<Value>(&contents) {
+            Ok(Value::Object(existing)) => existing,
+            _ => {
+                return Err(anyhow!(
+                    "Not overwriting '{}' as it isn't a JSON object",
+                    path.to_string_lossy()
+                ))
+            }
+        },
+        Err(_) => Map::new(),
+    };
+    for (key, value) in build_extra_network_info(model, model_version) {
+        let keep = merged.get(&key).is_some_and(|v| !is_empty(v));
+        if keep {
+            trace!(key, "Keeping existing value");
+        } else {
+            merged.insert(key, value);
+        }
+    }
+    write_json(&path, &Value::Object(merged))?;
+    debug!("Wrote {}", path.to_string_lossy());
+    Ok(path)
+}
+
+/// Maps Civitai's base model names onto the versions A1111 filters cards by.
+fn get_sd_version(base_model: Option<&str>) -> &'static str {
+    let base_model = base_model.unwrap_or_default().to_ascii_uppercase();
+    if base_model.starts_with("SDXL") {
+        "SDXL"
+    } else if base_model.starts_with("SD 1") {
+        "SD1"
+    } else if base_model.starts_with("SD 2") {
+        "SD2"
+    } else {
+        "Unknown"
+    }
+}
+
+/// Whether a field of an existing sidecar was never filled in. Numbers always count as set, since
+/// a weight of 0 may well be deliberate.
+fn is_empty(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::String(s) => s.trim().is_empty(),
+        _ => false,
+    }
+}
+
+/// Civitai descriptions are HTML, but the cards show plain text.
+fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// `<stem>.<suffix>` next to `model_path`.
+fn with_suffix(model_path: &Path, suffix: &str) -> PathBuf {
+    let stem = model_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    model_path.with_file_name(format!("{stem}.{suffix}"))
+}
+
+/// Writes pretty-printed JSON through a uniquely named temporary file, so readers never see half
+/// a sidecar and concurrent writers don't clobber each other's.
+fn write_json(path: &Path, value: &Value) -> anyhow::Result<()> {
+    let directory = path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let mut temp_file = NamedTempFile::new_in(directory).with_context(|| {
+        format!("Failed to create a temporary file in '{}'", directory.to_string_lossy())
+    })?;
+    temp_file
+        .write_all(&serde_json::to_vec_pretty(value)?)
+        .with_context(|| format!("Failed to write '{}'", temp_file.path().to_string_lossy()))?;
+    temp_file
+        .persist(path)
+        .with_context(|| format!("Failed to move sidecar to '{}'", path.to_string_lossy()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_values_set_by_hand() {
+        let directory = tempfile::tempdir().unwrap();
+        let model_path = directory.path().join("model.safetensors");
+        fs::write(
+            get_extra_network_path(&model_path),
+            r#"{"preferred weight": 0, "notes": "mine", "activation text": " ", "extra": true}"#,
+        )
+        .unwrap();
+        let model_version = ModelVersion {
+            trained_words: vec!["token".to_string()],
+            ..Default::default()
+        };
+
+        let path = write_extra_network_info(&model_path, &Model::default(), &model_version).unwrap();
+        let written: Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(written["preferred weight"], 0);
+        assert_eq!(written["notes"], "mine");
+        assert_eq!(written["activation text"], "token");
+        assert_eq!(written["extra"], true);
+    }
+}
@@ -0,0 +1,170 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use tracing::trace;
+
+/// A token bucket limiting how many bytes per second pass through it.
+///
+/// Clones share the same bucket, so one limiter can cap every concurrent download at once.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    bytes_per_second: u64,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    available: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        RateLimiter {
+            bytes_per_second: bytes_per_second.max(1),
+            bucket: Arc::new(Mutex::new(Bucket {
+                available: 0.0,
+                last_refill: Instant::now(),
+            })),
+        }
+    }
+
+    /// Takes `bytes` out of the bucket, sleeping for as long as it takes the bucket to pay them
+    /// back.
+    pub async fn consume(&self, bytes: u64) {
+        let wait = self.take(bytes, Instant::now());
+        if !wait.is_zero() {
+            trace!(bytes, wait =? wait, "Throttling download");
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Refills the bucket up to `now` and takes `bytes` out of it, returning how long to wait
+    /// for it to pay them back.
+    fn take(&self, bytes: u64, now: Instant) -> Duration {
+        let rate = self.bytes_per_second as f64;
+        let mut bucket = self.bucket.lock().unwrap();
+        let refill = now.saturating_duration_since(bucket.last_refill).as_secs_f64() * rate;
+        // Allow at most one second worth of burst after being idle.
+        bucket.available = (bucket.available + refill).min(rate);
+        bucket.last_refill = now;
+        bucket.available -= bytes as f64;
+        if bucket.available < 0.0 {
+            Duration::from_secs_f64(-bucket.available / rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+/// The limits applied to a single download: the global limit shared by every download, and an
+/// optional limit for this download alone.
+#[derive(Clone, Debug, Default)]
+pub struct Throttle {
+    global: Option<RateLimiter>,
+    per_download: Option<RateLimiter>,
+}
+
+impl Throttle {
+    pub fn new(global: Option<RateLimiter>, per_download_bytes_per_second: Option<u64>) -> Self {
+        Throttle {
+            global,
+            per_download: per_download_bytes_per_second.map(RateLimiter::new),
+        }
+    }
+
+    pub async fn consume(&self, bytes: u64) {
+        if let Some(limiter) = &self.per_download {
+            limiter.consume(bytes).await;
+        }
+        if let Some(limiter) = &self.global {
+            limiter.consume(bytes).await;
+        }
+    }
+}
+
+/// Parses a rate such as `500K`, `10M` or `1.5MiB` into bytes per second.
+///
+/// Suffixes are binary multiples, matching how the progress bars display sizes. Rates below one
+/// byte per second are rejected, since they would stall every download.
+pub fn parse_rate(value: &str) -> anyhow::Result<u64> {
+    let trimmed = value.trim().trim_end_matches("/s");
+    let split = trimmed
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| anyhow!("Invalid rate '{}'", value))?;
+    let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1u64,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        other => return Err(anyhow!("Unknown unit '{}' in rate '{}'", other, value)),
+    };
+    match (number * multiplier as f64) as u64 {
+        0 => Err(anyhow!(
+            "Rate '{}' is less than one byte per second. Leave it unset for no limit",
+            value
+        )),
+        rate => Ok(rate),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rates() {
+        assert_eq!(parse_rate("500").unwrap(), 500);
+        assert_eq!(parse_rate("500B").unwrap(), 500);
+        assert_eq!(parse_rate("500K").unwrap(), 500 * 1024);
+        assert_eq!(parse_rate("10 MB/s").unwrap(), 10 * 1024 * 1024);
+        assert_eq!(parse_rate("1.5MiB").unwrap(), 3 * 512 * 1024);
+        assert_eq!(parse_rate("2g").unwrap(), 2 * 1024 * 1024 * 1024);
+        assert_eq!(parse_rate(" 1k/s ").unwrap(), 1024);
+    }
+
+    #[test]
+    fn rejects_bad_rates() {
+        for value in ["", "fast", "10X", "1.2.3M", "-5K", "0", "0.1", "0K"] {
+            assert!(parse_rate(value).is_err(), "{value} should be rejected");
+        }
+    }
+
+    #[test]
+    fn refills_bucket_over_time() {
+        let limiter = RateLimiter::new(1000);
+        let start = limiter.bucket.lock().unwrap().last_refill;
+        // The bucket starts empty, so the first bytes have to be paid back.
+        assert_eq!(limiter.take(500, start), Duration::from_millis(500));
+        // Half a second later those are paid back, and the next ones are owed again.
+        assert_eq!(limiter.take(500, start + Duration::from_millis(500)), Duration::from_millis(500));
+        assert_eq!(
+            limiter.take(0, start + Duration::from_millis(1500)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn caps_burst_at_one_second() {
+        let limiter = RateLimiter::new(1000);
+        let start = limiter.bucket.lock().unwrap().last_refill;
+        // Idling for ten seconds only banks one second worth of bytes.
+        let later = start + Duration::from_secs(10);
+        assert_eq!(limiter.take(1000, later), Duration::ZERO);
+        assert_eq!(limiter.take(250, later), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn clones_share_the_bucket() {
+        let limiter = RateLimiter::new(1000);
+        let start = limiter.bucket.lock().unwrap().last_refill;
+        let clone = limiter.clone();
+        assert_eq!(limiter.take(500, start), Duration::from_millis(500));
+        assert_eq!(clone.take(500, start), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::manifest::Manifest;
+use crate::model::model_version::ModelVersion;
+use crate::model::Model;
+use crate::report::write_table;
+
+/// A model with a newer published version than any of the installed ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AvailableUpdate {
+    pub model: Model,
+    pub installed_version_id: i64,
+    /// The installed version's details, if it is still listed on the model.
+    pub installed: Option<ModelVersion>,
+    pub latest: ModelVersion,
+}
+
+/// The version ids installed for each model id, taken from the files in the manifest that still
+/// exist.
+pub fn get_installed_versions(manifest: &Manifest) -> BTreeMap<i64, Vec<i64>> {
+    let mut installed: BTreeMap<i64, Vec<i64>> = BTreeMap::new();
+    for entry in manifest.entries.iter().filter(|e| e.path.exists()) {
+        let versions = installed.entry(entry.model_id).or_default();
+        if !versions.contains(&entry.model_version_id) {
+            versions.push(entry.model_version_id);
+        }
+    }
+    installed
+}
+
+/// Compares the newest published version of `model` against the installed versions.
+pub fn find_update(model: &Model, installed_version_ids: &[i64]) -> Option<AvailableUpdate> {
+    let latest = get_latest_version(model)?;
+    if installed_version_ids.contains(&latest.id) {
+        return None;
+    }
+    let installed = model
+        .model_versions
+        .iter()
+        .filter(|v| installed_version_ids.contains(&v.id))
+        .max_by(|a, b| a.created_at.cmp(&b.created_at))
+        .cloned();
+    // A version that was removed from the model is older than anything still listed.
+    let installed_version_id = installed
+        .as_ref()
+        .map(|v| v.id)
+        .or(installed_version_ids.iter().max().copied())?;
+    if let Some(i) = &installed {
+        if i.created_at >= latest.created_at {
+            return None;
+        }
+    }
+    Some(AvailableUpdate {
+        model: model.clone(),
+        installed_version_id,
+        installed,
+        latest: latest.clone(),
+    })
+}
+
+/// The most recently created version of `model` that can be downloaded.
+pub fn get_latest_version(model: &Model) -> Option<&ModelVersion> {
+    // ISO 8601 timestamps sort chronologically; the API lists the newest first when they tie.
+    model
+        .model_versions
+        .iter()
+        .rev()
+        .filter(|v| v.is_available())
+        .max_by(|a, b| a.created_at.cmp(&b.created_at))
+}
+
+/// A table of [`AvailableUpdate`]s.
+pub struct Updates<'a>(pub &'a [AvailableUpdate]);
+
+impl fmt::Display for Updates<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = ["Model", "Installed", "Created", "Latest", "Created"];
+        let rows = self
+            .0
+            .iter()
+            .map(|u| {
+                [
+                    format!("{} ({})", u.model.id, u.model.name),
+                    match &u.installed {
+                        Some(v) => format!("{} ({})", v.id, v.name),
+                        None => format!("{} (no longer listed)", u.installed_version_id),
+                    },
+                    get_date(u.installed.as_ref()),
+                    format!("{} ({})", u.latest.id, u.latest.name),
+                    get_date(Some(&u.latest)),
+                ]
+            })
+            .collect::<Vec<_>>();
+        write_table(f, &header, &rows)?;
+        writeln!(f, "\n{} update(s) available", self.0.len())
+    }
+}
+
+fn get_date(version: Option<&ModelVersion>) -> String {
+    version
+        .and_then(|v| v.created_at.as_deref())
+        .map(|c| c.chars().take(10).collect())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_version(id: i64, created_at: &str) -> ModelVersion {
+        ModelVersion {
+            id,
+            model_id: 1,
+            created_at: Some(created_at.to_string()),
+            status: Some("Published".to_string()),
+            availability: Some("Public".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn get_model(versions: Vec<ModelVersion>) -> Model {
+        Model {
+            id: 1,
+            model_versions: versions,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn finds_newer_version() {
+        let model = get_model(vec![
+            get_version(3, "2024-03-01T00:00:00.000Z"),
+            get_version(2, "2024-02-01T00:00:00.000Z"),
+        ]);
+        let update = find_update(&model, &[2]).unwrap();
+        assert_eq!(update.latest.id, 3);
+        assert_eq!(update.installed_version_id, 2);
+        assert!(find_update(&model, &[3]).is_none());
+    }
+
+    #[test]
+    fn skips_versions_that_cannot_be_downloaded() {
+        let mut draft = get_version(5, "2024-05-01T00:00:00.000Z");
+        draft.status = Some("Draft".to_string());
+        let mut early_access = get_version(4, "2024-04-01T00:00:00.000Z");
+        early_access.availability = Some("EarlyAccess".to_string());
+        let model = get_model(vec![
+            draft,
+            early_access,
+            get_version(3, "2024-03-01T00:00:00.000Z"),
+            get_version(2, "2024-02-01T00:00:00.000Z"),
+        ]);
+        assert_eq!(get_latest_version(&model).unwrap().id, 3);
+        assert_eq!(find_update(&model, &[2]).unwrap().latest.id, 3);
+        assert!(find_update(&model, &[3]).is_none());
+    }
+}